@@ -0,0 +1,264 @@
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::led_matrix::percent_to_led_value;
+
+pub const IIO_DEVICES_ROOT: &str = "/sys/bus/iio/devices";
+pub const DEFAULT_BRIGHTNESS_CURVE: &str = "0:5,10:15,100:35,500:70,1000:100";
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+const LUX_SMOOTHING: f64 = 0.3; // Weight of the newest reading in the lux average
+const RAMP_STEP_PERCENT: f64 = 2.0; // Largest brightness change per poll, so changes fade in
+
+/// Maps ambient light to a brightness percentage by interpolating between points.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BrightnessCurve {
+    points: Vec<(f64, f64)>,
+}
+
+impl BrightnessCurve {
+    pub fn percent_at(&self, lux: f64) -> f64 {
+        let (first, last) = (self.points[0], self.points[self.points.len() - 1]);
+        if lux <= first.0 {
+            return first.1;
+        }
+        if lux >= last.0 {
+            return last.1;
+        }
+        self.points
+            .windows(2)
+            .find(|pair| lux <= pair[1].0)
+            .map(|pair| {
+                let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+                y0 + (y1 - y0) * (lux - x0) / (x1 - x0)
+            })
+            .unwrap_or(last.1)
+    }
+}
+
+// Points are written as `LUX:PERCENT` separated by commas, with increasing lux,
+// e.g. `0:5,100:35,1000:100`. Brightness is constant outside the given range.
+impl FromStr for BrightnessCurve {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut points: Vec<(f64, f64)> = Vec::new();
+        for point in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (lux, percent) = point
+                .split_once(':')
+                .ok_or_else(|| anyhow!("Expected LUX:PERCENT, got '{}'", point))?;
+            let lux: f64 = lux
+                .trim()
+                .parse()
+                .map_err(|e| anyhow!("Invalid lux in '{}': {}", point, e))?;
+            let percent: u8 = percent
+                .trim()
+                .parse()
+                .map_err(|e| anyhow!("Invalid percentage in '{}': {}", point, e))?;
+            if !lux.is_finite() || lux < 0.0 || percent > 100 {
+                return Err(anyhow!(
+                    "Curve point '{}' must have lux >= 0 and a percentage of 0-100",
+                    point
+                ));
+            }
+            if points.last().is_some_and(|&(last, _)| lux <= last) {
+                return Err(anyhow!("Curve points must be in increasing lux order"));
+            }
+            points.push((lux, percent as f64));
+        }
+
+        if points.is_empty() {
+            return Err(anyhow!("Brightness curve needs at least one point"));
+        }
+        Ok(BrightnessCurve { points })
+    }
+}
+
+/// Returns the illuminance file of the first IIO device that has one.
+pub fn find_sensor() -> Option<PathBuf> {
+    let mut sensors: Vec<PathBuf> = fs::read_dir(IIO_DEVICES_ROOT)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path().join("in_illuminance_raw"))
+        .filter(|path| path.is_file())
+        .collect();
+    sensors.sort();
+    sensors.into_iter().next()
+}
+
+/// Reads the sensor in lux, applying the device's `in_illuminance_scale` and
+/// `in_illuminance_offset` when it has them.
+pub fn read_lux(sensor: &Path) -> Result<f64> {
+    let raw: f64 = fs::read_to_string(sensor)
+        .with_context(|| format!("Failed reading {}", sensor.display()))?
+        .trim()
+        .parse()
+        .with_context(|| format!("Unexpected value in {}", sensor.display()))?;
+    let attribute = |name: &str| {
+        sensor
+            .parent()
+            .and_then(|dir| fs::read_to_string(dir.join(name)).ok())
+            .and_then(|value| value.trim().parse::<f64>().ok())
+    };
+    let offset = attribute("in_illuminance_offset").unwrap_or(0.0);
+    let scale = attribute("in_illuminance_scale").unwrap_or(1.0);
+    Ok(((raw + offset) * scale).max(0.0))
+}
+
+// Brightness percentages of the sensor thread: the target follows the curve once it moves
+// far enough, and the current value fades towards the target.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Ramp {
+    target: f64,
+    current: f64,
+}
+
+impl Ramp {
+    fn new(percent: f64) -> Self {
+        Ramp {
+            target: percent,
+            current: percent,
+        }
+    }
+
+    // One poll: takes `wanted` as the new target if it is at least `hysteresis` away, then
+    // moves one step towards the target. Returns the new brightness if it changed.
+    fn step(&mut self, wanted: f64, hysteresis: f64) -> Option<f64> {
+        if (wanted - self.target).abs() >= hysteresis {
+            self.target = wanted;
+        }
+        if self.current == self.target {
+            return None;
+        }
+        self.current += (self.target - self.current).clamp(-RAMP_STEP_PERCENT, RAMP_STEP_PERCENT);
+        Some(self.current)
+    }
+}
+
+/// Drives `brightness` from the ambient light sensor on a background thread. The target
+/// only moves once the curve output differs from it by at least `hysteresis` percent, and
+/// the brightness then ramps towards it in small steps.
+pub fn spawn(
+    sensor: PathBuf,
+    curve: BrightnessCurve,
+    hysteresis: f64,
+    brightness: Arc<AtomicU8>,
+) -> Result<()> {
+    let mut lux = read_lux(&sensor)?;
+    let mut ramp = Ramp::new(curve.percent_at(lux));
+    brightness.store(
+        percent_to_led_value(ramp.current.round() as u8),
+        Ordering::SeqCst,
+    );
+
+    thread::spawn(move || {
+        let mut failing = false;
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            match read_lux(&sensor) {
+                Ok(reading) => {
+                    failing = false;
+                    lux += LUX_SMOOTHING * (reading - lux);
+                }
+                Err(e) => {
+                    if !failing {
+                        warn!("Ambient light sensor unavailable: {:#}", e);
+                        failing = true;
+                    }
+                    continue;
+                }
+            }
+
+            if let Some(percent) = ramp.step(curve.percent_at(lux), hysteresis) {
+                brightness.store(
+                    percent_to_led_value(percent.round() as u8),
+                    Ordering::SeqCst,
+                );
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn curve_interpolates_and_clamps() {
+        let curve: BrightnessCurve = "10:20, 110:70,1000:100".parse().unwrap();
+        assert_eq!(curve.percent_at(0.0), 20.0);
+        assert_eq!(curve.percent_at(10.0), 20.0);
+        assert_eq!(curve.percent_at(60.0), 45.0);
+        assert_eq!(curve.percent_at(110.0), 70.0);
+        assert_eq!(curve.percent_at(5000.0), 100.0);
+
+        let flat: BrightnessCurve = "50:40".parse().unwrap();
+        assert_eq!(flat.percent_at(0.0), 40.0);
+        assert_eq!(flat.percent_at(1e6), 40.0);
+    }
+
+    #[test]
+    fn curve_rejects_bad_points() {
+        for bad in [
+            "",
+            "10",
+            "10:x",
+            "-1:20",
+            "10:101",
+            "inf:50",
+            "100:50,10:60",
+            "10:50,10:60",
+        ] {
+            assert!(bad.parse::<BrightnessCurve>().is_err(), "accepted '{}'", bad);
+        }
+    }
+
+    #[test]
+    fn lux_applies_offset_and_scale() {
+        let dir = TempDir::new().unwrap();
+        let sensor = dir.path().join("in_illuminance_raw");
+        fs::write(&sensor, "120\n").unwrap();
+        assert_eq!(read_lux(&sensor).unwrap(), 120.0);
+
+        fs::write(dir.path().join("in_illuminance_scale"), "0.5\n").unwrap();
+        fs::write(dir.path().join("in_illuminance_offset"), "-20\n").unwrap();
+        assert_eq!(read_lux(&sensor).unwrap(), 50.0);
+
+        fs::write(&sensor, "4\n").unwrap();
+        assert_eq!(read_lux(&sensor).unwrap(), 0.0);
+
+        fs::write(&sensor, "dark\n").unwrap();
+        assert!(read_lux(&sensor).is_err());
+        assert!(read_lux(&dir.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn ramp_ignores_changes_within_hysteresis() {
+        let mut ramp = Ramp::new(50.0);
+        assert_eq!(ramp.step(54.9, 5.0), None);
+        assert_eq!(ramp.step(45.1, 5.0), None);
+        assert_eq!(ramp, Ramp::new(50.0));
+    }
+
+    #[test]
+    fn ramp_steps_towards_new_target() {
+        let mut ramp = Ramp::new(50.0);
+        assert_eq!(ramp.step(55.0, 5.0), Some(52.0));
+        // Still close to the target, so the ramp keeps going.
+        assert_eq!(ramp.step(53.0, 5.0), Some(54.0));
+        assert_eq!(ramp.step(53.0, 5.0), Some(55.0));
+        assert_eq!(ramp.step(53.0, 5.0), None);
+
+        assert_eq!(ramp.step(40.0, 5.0), Some(53.0));
+        assert_eq!(ramp.target, 40.0);
+    }
+}
@@ -0,0 +1,431 @@
+use anyhow::{anyhow, Context, Result};
+use clap::builder::Resettable;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml::{Table, Value};
+
+// Options that choose the settings file, so they cannot come from it.
+const COMMAND_LINE_ONLY: [&str; 4] = ["config", "profile", "help", "version"];
+
+/// `$XDG_CONFIG_HOME/fw16-pong-wars/config.toml`, or under `~/.config` when that is unset.
+pub fn default_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(base.join("fw16-pong-wars").join("config.toml"))
+}
+
+/// Settings file. Keys are the long command line options (`speed = 48`,
+/// `battery-saver = true`); a table prefixes its keys with its name (`[battery] fps = 30`
+/// is `battery-fps`). `[physics]`, `[orientation]` and `[[modules]]` are written as tables
+/// instead of the option strings, and `[profiles.NAME]` sections override the rest when
+/// chosen with `--profile`.
+pub struct Config {
+    path: PathBuf,
+    base: BTreeMap<String, Value>,
+    profiles: BTreeMap<String, BTreeMap<String, Value>>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed reading {}", path.display()))?;
+        Self::parse(path, &text)
+    }
+
+    fn parse(path: &Path, text: &str) -> Result<Self> {
+        let mut table: Table = text
+            .parse()
+            .with_context(|| format!("Failed parsing {}", path.display()))?;
+
+        let profiles = match table.remove("profiles") {
+            None => BTreeMap::new(),
+            Some(Value::Table(profiles)) => profiles
+                .into_iter()
+                .map(|(name, profile)| match profile {
+                    Value::Table(settings) => flatten(settings)
+                        .with_context(|| format!("In [profiles.{}]", name))
+                        .map(|settings| (name, settings)),
+                    _ => Err(anyhow!("profiles.{} must be a [profiles.{}] section", name, name)),
+                })
+                .collect::<Result<_>>()?,
+            Some(_) => return Err(anyhow!("profiles must be [profiles.NAME] sections")),
+        };
+
+        Ok(Config {
+            path: path.to_path_buf(),
+            base: flatten(table)?,
+            profiles,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn profile_names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    // The main settings with `profile` applied on top. Main settings for options that cannot
+    // be used together with one the profile turns on are dropped, so a profile can switch
+    // e.g. from `dualmode` to `mirror`.
+    fn settings(&self, profile: Option<&str>, command: &Command) -> Result<BTreeMap<String, Value>> {
+        let mut settings = self.base.clone();
+        if let Some(name) = profile {
+            let overrides = self.profiles.get(name).ok_or_else(|| {
+                let available: Vec<&str> = self.profile_names().collect();
+                anyhow!(
+                    "No profile '{}' in {} (available: {})",
+                    name,
+                    self.path.display(),
+                    if available.is_empty() { "none".to_string() } else { available.join(", ") }
+                )
+            })?;
+            let enabled: Vec<&Arg> = overrides
+                .iter()
+                .filter(|(_, value)| **value != Value::Boolean(false))
+                .filter_map(|(key, _)| find_arg(command, key))
+                .collect();
+            settings.retain(|key, _| {
+                find_arg(command, key)
+                    .is_none_or(|arg| !enabled.iter().any(|other| overridden_by(command, arg, other)))
+            });
+            settings.extend(overrides.clone());
+        }
+        Ok(settings)
+    }
+
+    /// Value of a single setting, e.g. the control socket for `ctl`.
+    pub fn get(&self, profile: Option<&str>, command: &Command, name: &str) -> Result<Option<String>> {
+        self.settings(profile, command)?
+            .get(name)
+            .map(|value| scalar(name, value))
+            .transpose()
+    }
+
+    /// Turns the settings into command line arguments for `command`. Settings for options
+    /// that `cli` got on the command line, or that conflict with one it got, are left out so
+    /// the command line wins.
+    pub fn to_args(&self, profile: Option<&str>, command: &Command, cli: &ArgMatches) -> Result<Vec<String>> {
+        let given: Vec<&Arg> = command
+            .get_arguments()
+            .filter(|arg| cli.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine))
+            .collect();
+
+        let mut args = Vec::new();
+        for (name, value) in self.settings(profile, command)? {
+            if COMMAND_LINE_ONLY.contains(&name.as_str()) {
+                return Err(anyhow!("{} can only be given on the command line", name));
+            }
+            let arg = find_arg(command, &name)
+                .ok_or_else(|| anyhow!("Unknown setting '{}' in {}", name, self.path.display()))?;
+            if given.iter().any(|other| overridden_by(command, arg, other)) {
+                continue;
+            }
+
+            match value {
+                Value::Boolean(enabled) if !arg.get_action().takes_values() => {
+                    if enabled {
+                        args.push(format!("--{}", name));
+                    }
+                }
+                _ if !arg.get_action().takes_values() => {
+                    return Err(anyhow!("{} must be true or false", name));
+                }
+                Value::Array(values) => {
+                    for value in &values {
+                        args.push(format!("--{}={}", name, scalar(&name, value)?));
+                    }
+                }
+                value => args.push(format!("--{}={}", name, scalar(&name, &value)?)),
+            }
+        }
+        Ok(args)
+    }
+}
+
+/// Adds a hidden `--no-NAME` for every on/off option, which turns it off again when given
+/// later, e.g. `--no-mirror` after a `--mirror` that came from the settings file.
+pub fn with_negations(command: Command) -> Command {
+    let negations: Vec<Arg> = command
+        .get_arguments()
+        .filter(|arg| matches!(arg.get_action(), ArgAction::SetTrue) && !arg.is_global_set())
+        .filter_map(|arg| {
+            let long = arg.get_long().filter(|long| !long.starts_with("no-"))?;
+            Some(
+                Arg::new(format!("no_{}", arg.get_id()))
+                    .long(format!("no-{}", long))
+                    .help(format!("Turn off --{}", long))
+                    .action(ArgAction::SetTrue)
+                    .overrides_with(arg.get_id())
+                    .hide(true),
+            )
+        })
+        .collect();
+    command.args(negations)
+}
+
+/// A copy of `command` that does not check `requires`, for reading the command line before
+/// the settings file is merged in: the file may turn on the option another one needs.
+pub fn without_requirements(command: &Command) -> Command {
+    command.clone().mut_args(|arg| arg.requires(Resettable::Reset))
+}
+
+fn find_arg<'a>(command: &'a Command, name: &str) -> Option<&'a Arg> {
+    command.get_arguments().find(|arg| arg.get_long() == Some(name))
+}
+
+// Whether `other` from the command line replaces the setting for `arg`: it is the same
+// option, or the two cannot be used together.
+fn overridden_by(command: &Command, arg: &Arg, other: &Arg) -> bool {
+    let id = arg.get_id();
+    let other_id = other.get_id();
+    id == other_id
+        || command
+            .get_arg_conflicts_with(arg)
+            .iter()
+            .any(|conflict| conflict.get_id() == other_id)
+        || command
+            .get_arg_conflicts_with(other)
+            .iter()
+            .any(|conflict| conflict.get_id() == id)
+        || command.get_groups().any(|group| {
+            !group.clone().is_multiple()
+                && group.get_args().any(|member| member == id)
+                && group.get_args().any(|member| member == other_id)
+        })
+}
+
+fn flatten(table: Table) -> Result<BTreeMap<String, Value>> {
+    let mut settings = BTreeMap::new();
+    flatten_into(&mut settings, "", table)?;
+    Ok(settings)
+}
+
+fn flatten_into(settings: &mut BTreeMap<String, Value>, prefix: &str, table: Table) -> Result<()> {
+    for (key, value) in table {
+        let mut name = format!("{}{}", prefix, key);
+        let value = match (name.as_str(), value) {
+            ("physics" | "right-physics", Value::Table(physics)) => Value::String(
+                physics
+                    .iter()
+                    .map(|(key, value)| Ok(format!("{}={}", key, scalar(&name, value)?)))
+                    .collect::<Result<Vec<_>>>()?
+                    .join(","),
+            ),
+            ("orientation", Value::Table(orientations)) => Value::Array(
+                orientations
+                    .iter()
+                    .map(|(serial, value)| Ok(Value::String(format!("{}={}", serial, scalar(&name, value)?))))
+                    .collect::<Result<_>>()?,
+            ),
+            ("modules", Value::Array(modules)) => {
+                name = "layout".to_string();
+                Value::String(layout(modules)?)
+            }
+            (_, Value::Table(table)) => {
+                flatten_into(settings, &format!("{}-", name), table)?;
+                continue;
+            }
+            (_, value) => value,
+        };
+        if settings.insert(name.clone(), value).is_some() {
+            return Err(anyhow!("{} is set more than once", name));
+        }
+    }
+    Ok(())
+}
+
+// `[[modules]]` entries in the `--layout` format, in the order they are listed.
+fn layout(modules: Vec<Value>) -> Result<String> {
+    let mut entries = Vec::new();
+    for module in modules {
+        let Value::Table(mut module) = module else {
+            return Err(anyhow!("Each [[modules]] entry must be a table"));
+        };
+        let mut field = |key: &str| module.remove(key).map(|value| scalar(key, &value)).transpose();
+        let serial = field("serial")?.ok_or_else(|| anyhow!("[[modules]] entry without a serial"))?;
+        let x = field("x")?.unwrap_or_else(|| "0".to_string());
+        let y = field("y")?.unwrap_or_else(|| "0".to_string());
+        let mut entry = format!("{}@{},{}", serial, x, y);
+        if let Some(orientation) = field("orientation")? {
+            entry = format!("{}:{}", entry, orientation);
+        }
+        if let Some(unknown) = module.keys().next() {
+            return Err(anyhow!("Unknown key '{}' in the [[modules]] entry for {}", unknown, serial));
+        }
+        entries.push(entry);
+    }
+    Ok(entries.join(";"))
+}
+
+fn scalar(name: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        _ => Err(anyhow!("{} must be a string or a number", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_command() -> Command {
+        let mut command = with_negations(
+            Command::new("test")
+                .arg(Arg::new("dual_mode").long("dualmode").action(ArgAction::SetTrue))
+                .arg(
+                    Arg::new("mirror")
+                        .long("mirror")
+                        .action(ArgAction::SetTrue)
+                        .conflicts_with("dual_mode"),
+                )
+                .arg(Arg::new("speed").long("speed").value_parser(clap::value_parser!(u8)))
+                .arg(Arg::new("physics").long("physics"))
+                .arg(Arg::new("layout").long("layout"))
+                .arg(Arg::new("orientation").long("orientation").action(ArgAction::Append))
+                .arg(
+                    Arg::new("split")
+                        .long("split")
+                        .action(ArgAction::SetTrue)
+                        .requires("dual_mode"),
+                )
+                .arg(Arg::new("battery_fps").long("battery-fps")),
+        );
+        command.build();
+        command
+    }
+
+    fn config(text: &str) -> Config {
+        Config::parse(Path::new("test.toml"), text).unwrap()
+    }
+
+    fn to_args(config: &Config, profile: Option<&str>, cli: &[&str]) -> Result<Vec<String>> {
+        let command = test_command();
+        let matches = without_requirements(&command)
+            .try_get_matches_from(std::iter::once("test").chain(cli.iter().copied()))
+            .unwrap();
+        config.to_args(profile, &command, &matches)
+    }
+
+    #[test]
+    fn flatten_turns_tables_into_option_strings() {
+        let config = config(
+            r#"
+            speed = 40
+            [battery]
+            fps = 30
+            [physics]
+            max = 0.6
+            min = 0.25
+            [orientation]
+            FRAKDEAM1 = "rotate180"
+            [[modules]]
+            serial = "FRAKDEAM1"
+            [[modules]]
+            serial = "FRAKDEAM2"
+            x = 9
+            orientation = "flip-h"
+            "#,
+        );
+        assert_eq!(
+            to_args(&config, None, &[]).unwrap(),
+            [
+                "--battery-fps=30",
+                "--layout=FRAKDEAM1@0,0;FRAKDEAM2@9,0:flip-h",
+                "--orientation=FRAKDEAM1=rotate180",
+                "--physics=max=0.6,min=0.25",
+                "--speed=40",
+            ]
+        );
+    }
+
+    #[test]
+    fn flatten_rejects_bad_tables() {
+        for bad in [
+            "battery-fps = 30\n[battery]\nfps = 20",
+            "[[modules]]\nx = 9",
+            "[[modules]]\nserial = \"A\"\nwidth = 9",
+            "[physics]\nmax = [1]",
+        ] {
+            let table: Table = bad.parse().unwrap();
+            assert!(flatten(table).is_err(), "accepted '{}'", bad);
+        }
+    }
+
+    #[test]
+    fn to_args_leaves_out_what_the_command_line_overrides() {
+        let config = config("dualmode = true\nspeed = 40\n");
+        assert_eq!(to_args(&config, None, &[]).unwrap(), ["--dualmode", "--speed=40"]);
+        assert_eq!(to_args(&config, None, &["--speed", "20"]).unwrap(), ["--dualmode"]);
+        assert_eq!(to_args(&config, None, &["--mirror"]).unwrap(), ["--speed=40"]);
+    }
+
+    // Parses the settings followed by the command line, as `load_args` does.
+    fn merged(text: &str, cli: &[&str]) -> Result<ArgMatches, clap::Error> {
+        let settings = to_args(&config(text), None, cli).unwrap();
+        let argv = ["test"]
+            .into_iter()
+            .chain(settings.iter().map(String::as_str))
+            .chain(cli.iter().copied());
+        test_command().try_get_matches_from(argv)
+    }
+
+    #[test]
+    fn command_line_options_may_need_one_from_the_file() {
+        assert!(test_command().try_get_matches_from(["test", "--split"]).is_err());
+
+        let matches = merged("dualmode = true", &["--split"]).unwrap();
+        assert!(matches.get_flag("dual_mode"));
+        assert!(matches.get_flag("split"));
+        assert!(merged("", &["--split"]).is_err());
+    }
+
+    #[test]
+    fn to_args_checks_settings() {
+        for bad in ["profile = \"x\"", "unknown = 1", "dualmode = 1", "speed = [1, [2]]"] {
+            assert!(to_args(&config(bad), None, &[]).is_err(), "accepted '{}'", bad);
+        }
+        assert_eq!(to_args(&config("dualmode = false"), None, &[]).unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn profiles_replace_conflicting_settings() {
+        let config = config(
+            r#"
+            dualmode = true
+            speed = 40
+            [profiles.mirror]
+            mirror = true
+            [profiles.off]
+            mirror = false
+            speed = 20
+            "#,
+        );
+        assert_eq!(to_args(&config, Some("mirror"), &[]).unwrap(), ["--mirror", "--speed=40"]);
+        assert_eq!(to_args(&config, Some("off"), &[]).unwrap(), ["--dualmode", "--speed=20"]);
+        assert!(to_args(&config, Some("missing"), &[]).is_err());
+        assert_eq!(
+            config.get(Some("off"), &test_command(), "speed").unwrap().as_deref(),
+            Some("20")
+        );
+    }
+
+    #[test]
+    fn negations_turn_off_flags_from_the_settings_file() {
+        let command = test_command();
+        let matches = command
+            .try_get_matches_from(["test", "--dualmode", "--no-dualmode", "--mirror"])
+            .unwrap();
+        assert!(!matches.get_flag("dual_mode"));
+        assert!(matches.get_flag("mirror"));
+        assert!(test_command().try_get_matches_from(["test", "--no-speed"]).is_err());
+    }
+}
@@ -0,0 +1,351 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::time::Duration;
+
+use crate::scene::{score_line, Score};
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A command for the running instance, sent over the control socket as one JSON object
+/// per line, e.g. `{"cmd":"brightness","percent":40}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "cmd", rename_all = "kebab-case")]
+pub enum Command {
+    Status,
+    Brightness { percent: u8 },
+    Speed { fps: u8 },
+    Pause,
+    Resume,
+    TogglePause,
+    /// Pause if needed and advance the game by a single frame.
+    Step,
+    Reset,
+    /// Show or hide the frame rate overlay on the matrix.
+    ToggleOverlay,
+    Scene { name: String },
+    Quit,
+}
+
+/// Snapshot of the running instance returned for `status`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Status {
+    pub scene: String,
+    pub width: usize,
+    pub height: usize,
+    pub fps: u8,
+    pub speed: u8,
+    /// Requested brightness percentage.
+    pub brightness: u8,
+    /// Brightness percentage actually sent, lower than requested while on battery.
+    pub applied_brightness: u8,
+    pub paused: bool,
+    pub modules: String,
+    pub scores: Vec<Score>,
+    pub frames: u64,
+    /// Slowest smoothed serial write time of any module.
+    pub write_latency_ms: Option<f64>,
+    pub write_errors: u64,
+    pub dropped_frames: u64,
+    /// How late the frame loop woke up for its deadlines.
+    pub mean_jitter_ms: f64,
+    pub max_jitter_ms: f64,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Scene: {} ({}x{})", self.scene, self.width, self.height)?;
+        writeln!(f, "Frame rate: {} fps (target {})", self.fps, self.speed)?;
+        if self.applied_brightness == self.brightness {
+            writeln!(f, "Brightness: {}%", self.brightness)?;
+        } else {
+            writeln!(
+                f,
+                "Brightness: {}% ({}% while on battery)",
+                self.brightness, self.applied_brightness
+            )?;
+        }
+        writeln!(f, "Paused: {}", if self.paused { "yes" } else { "no" })?;
+        writeln!(f, "Modules: {}", self.modules)?;
+        writeln!(f, "Scores: {}", score_line(&self.scores))?;
+        let latency = self
+            .write_latency_ms
+            .map_or_else(|| "n/a".to_string(), |ms| format!("{:.2} ms", ms));
+        writeln!(
+            f,
+            "Writes: latency {}, {} errors, {} dropped frames",
+            latency, self.write_errors, self.dropped_frames
+        )?;
+        write!(
+            f,
+            "Timing: {} frames, mean jitter {:.3} ms, max {:.3} ms",
+            self.frames, self.mean_jitter_ms, self.max_jitter_ms
+        )
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Reply {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<Status>,
+}
+
+impl Reply {
+    pub fn ok() -> Self {
+        Reply {
+            ok: true,
+            error: None,
+            status: None,
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Reply {
+            ok: false,
+            error: Some(message.into()),
+            status: None,
+        }
+    }
+
+    pub fn status(status: Status) -> Self {
+        Reply {
+            status: Some(status),
+            ..Reply::ok()
+        }
+    }
+}
+
+/// A command waiting for the frame loop, which answers through `reply`.
+pub struct Request {
+    pub command: Command,
+    reply: Sender<Reply>,
+}
+
+impl Request {
+    pub fn respond(self, reply: Reply) {
+        let _ = self.reply.send(reply);
+    }
+}
+
+/// Cloneable handle for submitting commands to the frame loop from other threads.
+#[derive(Clone)]
+pub struct Controls {
+    sender: Sender<Request>,
+}
+
+impl Controls {
+    /// Returns the handle and the receiver the frame loop drains every iteration.
+    pub fn new() -> (Self, Receiver<Request>) {
+        let (sender, receiver) = mpsc::channel();
+        (Controls { sender }, receiver)
+    }
+
+    /// Sends `command` and waits for the frame loop to handle it.
+    pub fn submit(&self, command: Command) -> Reply {
+        let (reply, response) = mpsc::channel();
+        if self.sender.send(Request { command, reply }).is_err() {
+            return Reply::error("shutting down");
+        }
+        response
+            .recv_timeout(REPLY_TIMEOUT)
+            .unwrap_or_else(|_| Reply::error("no response from the frame loop"))
+    }
+}
+
+/// `$XDG_RUNTIME_DIR/fw16-pong-wars.sock`, or the temp directory if that is not set.
+pub fn default_socket_path() -> PathBuf {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("fw16-pong-wars.sock")
+}
+
+#[cfg(unix)]
+pub use socket::{send, ControlServer};
+
+#[cfg(not(unix))]
+pub fn send(_path: &std::path::Path, _command: &Command) -> anyhow::Result<Reply> {
+    Err(anyhow::anyhow!(
+        "The control socket is only available on Unix"
+    ))
+}
+
+#[cfg(unix)]
+mod socket {
+    use super::{Command, Controls, Reply};
+    use anyhow::{anyhow, Context, Result};
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::{Path, PathBuf};
+    use std::thread;
+
+    /// Listens on the control socket and forwards commands to the frame loop. The socket
+    /// file is removed when the server is dropped.
+    pub struct ControlServer {
+        path: PathBuf,
+    }
+
+    impl ControlServer {
+        pub fn spawn(path: PathBuf, controls: Controls) -> Result<Self> {
+            if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+                if !metadata.file_type().is_socket() {
+                    return Err(anyhow!("{} exists and is not a socket", path.display()));
+                }
+                if UnixStream::connect(&path).is_ok() {
+                    return Err(anyhow!(
+                        "Another instance is already listening on {}",
+                        path.display()
+                    ));
+                }
+                // Left behind by an instance that did not exit cleanly.
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Failed removing stale {}", path.display()))?;
+            }
+            let listener = UnixListener::bind(&path)
+                .with_context(|| format!("Failed binding {}", path.display()))?;
+
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let controls = controls.clone();
+                    thread::spawn(move || serve(stream, controls));
+                }
+            });
+
+            Ok(ControlServer { path })
+        }
+    }
+
+    impl Drop for ControlServer {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+
+    fn serve(stream: UnixStream, controls: Controls) {
+        let Ok(mut writer) = stream.try_clone() else {
+            return;
+        };
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else {
+                return;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let reply = match serde_json::from_str::<Command>(&line) {
+                Ok(command) => controls.submit(command),
+                Err(e) => Reply::error(format!("invalid command: {}", e)),
+            };
+            let Ok(mut encoded) = serde_json::to_string(&reply) else {
+                return;
+            };
+            encoded.push('\n');
+            if writer.write_all(encoded.as_bytes()).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Sends one command to a running instance and returns its reply.
+    pub fn send(path: &Path, command: &Command) -> Result<Reply> {
+        let mut stream = UnixStream::connect(path)
+            .with_context(|| format!("No running instance listening on {}", path.display()))?;
+        let mut line = serde_json::to_string(command)?;
+        line.push('\n');
+        stream.write_all(line.as_bytes())?;
+
+        let mut response = String::new();
+        BufReader::new(stream).read_line(&mut response)?;
+        if response.is_empty() {
+            return Err(anyhow!("The running instance closed the connection"));
+        }
+        Ok(serde_json::from_str(&response)?)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::control::Command;
+        use tempfile::TempDir;
+
+        #[test]
+        fn refuses_to_replace_other_files() {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("keepme.txt");
+            std::fs::write(&path, "data").unwrap();
+            let (controls, _requests) = Controls::new();
+
+            let error = ControlServer::spawn(path.clone(), controls).err().unwrap();
+            assert!(error.to_string().contains("is not a socket"), "{}", error);
+            assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+        }
+
+        #[test]
+        fn replaces_stale_sockets_but_not_live_ones() {
+            let dir = TempDir::new().unwrap();
+            let path = dir.path().join("control.sock");
+            drop(UnixListener::bind(&path).unwrap());
+            let (controls, requests) = Controls::new();
+
+            let server = ControlServer::spawn(path.clone(), controls.clone()).unwrap();
+            let error = ControlServer::spawn(path.clone(), controls).err().unwrap();
+            assert!(error.to_string().contains("Another instance"), "{}", error);
+
+            thread::spawn(move || {
+                for request in requests {
+                    let reply = match request.command {
+                        Command::Pause => Reply::ok(),
+                        _ => Reply::error("unexpected"),
+                    };
+                    request.respond(reply);
+                }
+            });
+            assert!(send(&path, &Command::Pause).unwrap().ok);
+
+            drop(server);
+            assert!(!path.exists());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_use_the_documented_wire_format() {
+        let cases = [
+            (r#"{"cmd":"brightness","percent":40}"#, Command::Brightness { percent: 40 }),
+            (r#"{"cmd":"speed","fps":32}"#, Command::Speed { fps: 32 }),
+            (r#"{"cmd":"toggle-pause"}"#, Command::TogglePause),
+            (r#"{"cmd":"toggle-overlay"}"#, Command::ToggleOverlay),
+            (r#"{"cmd":"scene","name":"pong-wars"}"#, Command::Scene { name: "pong-wars".to_string() }),
+            (r#"{"cmd":"status"}"#, Command::Status),
+        ];
+        for (json, command) in cases {
+            assert_eq!(serde_json::from_str::<Command>(json).unwrap(), command);
+            assert_eq!(serde_json::to_string(&command).unwrap(), json);
+        }
+        assert!(serde_json::from_str::<Command>(r#"{"cmd":"brightness"}"#).is_err());
+        assert!(serde_json::from_str::<Command>(r#"{"cmd":"explode"}"#).is_err());
+    }
+
+    #[test]
+    fn replies_leave_out_empty_fields() {
+        assert_eq!(serde_json::to_string(&Reply::ok()).unwrap(), r#"{"ok":true}"#);
+        assert_eq!(
+            serde_json::to_string(&Reply::error("brightness must be 0-100")).unwrap(),
+            r#"{"ok":false,"error":"brightness must be 0-100"}"#
+        );
+
+        let reply: Reply = serde_json::from_str(r#"{"ok":false,"error":"no"}"#).unwrap();
+        assert!(!reply.ok);
+        assert_eq!(reply.error.as_deref(), Some("no"));
+        assert!(reply.status.is_none());
+    }
+}
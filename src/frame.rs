@@ -0,0 +1,52 @@
+/// Monochrome frame buffer that scenes draw into and `LedMatrix` packs for the modules.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Self {
+        Frame {
+            width,
+            height,
+            pixels: vec![false; width * height],
+        }
+    }
+
+    #[inline]
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    #[inline]
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    #[inline]
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.pixels[y * self.width + x]
+    }
+
+    #[inline]
+    pub fn set(&mut self, x: usize, y: usize, on: bool) {
+        if x < self.width && y < self.height {
+            self.pixels[y * self.width + x] = on;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill(false);
+    }
+
+    /// Copies `other` into this frame with its left edge at column `x_offset`.
+    pub fn blit(&mut self, other: &Frame, x_offset: usize) {
+        for y in 0..other.height {
+            for x in 0..other.width {
+                self.set(x_offset + x, y, other.get(x, y));
+            }
+        }
+    }
+}
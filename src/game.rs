@@ -1,11 +1,77 @@
-use rand::Rng;
+use anyhow::{anyhow, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::str::FromStr;
+use std::time::Duration;
 
-pub const DEFAULT_GRID_HEIGHT: usize = 34;
+use crate::frame::Frame;
+use crate::scene::{Scene, SceneCommand, Score};
 
+const BASE_SPEED: f32 = 0.3;
 const MIN_SPEED: f32 = 0.2;
 const MAX_SPEED: f32 = 0.5;
 const SPEED_RANDOMNESS: f32 = 0.001;
 
+/// Ball speed tuning, in grid cells per frame.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Physics {
+    pub base_speed: f32,
+    pub min_speed: f32,
+    pub max_speed: f32,
+    pub speed_randomness: f32,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Physics {
+            base_speed: BASE_SPEED,
+            min_speed: MIN_SPEED,
+            max_speed: MAX_SPEED,
+            speed_randomness: SPEED_RANDOMNESS,
+        }
+    }
+}
+
+// Parses `key=value` pairs separated by commas, e.g. `base=0.3,min=0.2,max=0.5,randomness=0.001`.
+// Keys that are left out keep their default value.
+impl FromStr for Physics {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut physics = Physics::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Expected key=value, got '{}'", pair))?;
+            let value: f32 = value
+                .trim()
+                .parse()
+                .map_err(|e| anyhow!("Invalid value for '{}': {}", key.trim(), e))?;
+            if !value.is_finite() {
+                return Err(anyhow!("Invalid value for '{}': must be finite", key.trim()));
+            }
+            match key.trim() {
+                "base" => physics.base_speed = value,
+                "min" => physics.min_speed = value,
+                "max" => physics.max_speed = value,
+                "randomness" => physics.speed_randomness = value,
+                other => return Err(anyhow!("Unknown physics setting '{}'", other)),
+            }
+        }
+
+        if physics.min_speed <= 0.0 || physics.min_speed > physics.max_speed {
+            return Err(anyhow!("Physics requires 0 < min <= max"));
+        }
+        if physics.max_speed >= 1.0 {
+            return Err(anyhow!("Physics max speed must stay below one cell per frame"));
+        }
+        if physics.base_speed <= 0.0 || physics.speed_randomness < 0.0 {
+            return Err(anyhow!("Physics requires base > 0 and randomness >= 0"));
+        }
+        Ok(physics)
+    }
+}
+
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum SquareColor {
     Day,
@@ -43,11 +109,14 @@ pub struct GameState {
     pub balls: [Ball; 2],
     pub day_score: usize,
     pub night_score: usize,
-    pub rng: rand::rngs::ThreadRng,
+    pub rng: StdRng,
+    physics: Physics,
 }
 
 impl GameState {
-    pub fn new(width: usize, height: usize) -> Self {
+    /// Creates a game with the given physics; a fixed `seed` makes the game reproducible.
+    #[allow(clippy::needless_range_loop)]
+    pub fn with_options(width: usize, height: usize, physics: Physics, seed: Option<u64>) -> Self {
         assert!(width > 0, "width must be positive");
         assert!(height > 0, "height must be positive");
 
@@ -62,9 +131,12 @@ impl GameState {
             }
         }
 
-        let mut rng = rand::thread_rng();
-        let base_speed = 0.3;
-        
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let base_speed = physics.base_speed;
+
         let left_x = 2.0;
         let right_x = width_f32 - 2.0;
         let top_y = 2.0;
@@ -104,6 +176,7 @@ impl GameState {
             day_score,
             night_score,
             rng,
+            physics,
         }
     }
 
@@ -117,7 +190,46 @@ impl GameState {
         self.height
     }
 
+    /// Changes the grid size in place. Existing tiles keep their color, new tiles start out
+    /// like a fresh board and the balls are pulled back inside the grid.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        assert!(width > 0, "width must be positive");
+        assert!(height > 0, "height must be positive");
+
+        let half_height = height / 2;
+        let mut squares = vec![vec![SquareColor::Day; height]; width];
+        for (x, column) in squares.iter_mut().enumerate() {
+            for (y, square) in column.iter_mut().enumerate() {
+                *square = if x < self.width && y < self.height {
+                    self.squares[x][y]
+                } else if y < half_height {
+                    SquareColor::Night
+                } else {
+                    SquareColor::Day
+                };
+            }
+        }
+
+        self.width = width;
+        self.height = height;
+        self.width_f32 = width as f32;
+        self.height_f32 = height as f32;
+        self.day_score = squares
+            .iter()
+            .flatten()
+            .filter(|&&square| square == SquareColor::Day)
+            .count();
+        self.night_score = width * height - self.day_score;
+        self.squares = squares;
+
+        for ball in self.balls.iter_mut() {
+            ball.x = ball.x.clamp(0.5, (self.width_f32 - 1.0).max(0.5));
+            ball.y = ball.y.clamp(0.5, (self.height_f32 - 1.0).max(0.5));
+        }
+    }
+
     #[inline]
+    #[allow(clippy::assign_op_pattern, clippy::collapsible_if)]
     pub fn update(&mut self) {
         let mut new_squares = self.squares.clone();
         let mut day_score_delta = 0i32;
@@ -214,24 +326,33 @@ impl GameState {
             ball_state.x += ball_state.dx;
             ball_state.y += ball_state.dy;
 
-            ball_state.dx += self.rng.gen_range(-SPEED_RANDOMNESS..SPEED_RANDOMNESS);
-            ball_state.dy += self.rng.gen_range(-SPEED_RANDOMNESS..SPEED_RANDOMNESS);
+            let Physics {
+                min_speed,
+                max_speed,
+                speed_randomness,
+                ..
+            } = self.physics;
+
+            if speed_randomness > 0.0 {
+                ball_state.dx += self.rng.gen_range(-speed_randomness..speed_randomness);
+                ball_state.dy += self.rng.gen_range(-speed_randomness..speed_randomness);
+            }
 
-            ball_state.dx = ball_state.dx.clamp(-MAX_SPEED, MAX_SPEED);
-            ball_state.dy = ball_state.dy.clamp(-MAX_SPEED, MAX_SPEED);
+            ball_state.dx = ball_state.dx.clamp(-max_speed, max_speed);
+            ball_state.dy = ball_state.dy.clamp(-max_speed, max_speed);
 
-            if ball_state.dx.abs() < MIN_SPEED {
+            if ball_state.dx.abs() < min_speed {
                 ball_state.dx = if ball_state.dx > 0.0 {
-                    MIN_SPEED
+                    min_speed
                 } else {
-                    -MIN_SPEED
+                    -min_speed
                 };
             }
-            if ball_state.dy.abs() < MIN_SPEED {
+            if ball_state.dy.abs() < min_speed {
                 ball_state.dy = if ball_state.dy > 0.0 {
-                    MIN_SPEED
+                    min_speed
                 } else {
-                    -MIN_SPEED
+                    -min_speed
                 };
             }
 
@@ -245,3 +366,90 @@ impl GameState {
         self.night_score = (self.night_score as i32 + night_score_delta).max(0) as usize;
     }
 }
+
+impl Scene for GameState {
+    fn name(&self) -> &'static str {
+        "pong-wars"
+    }
+
+    // The simulation moves a fixed step per frame, so speed is controlled by the frame rate.
+    fn tick(&mut self, _dt: Duration) {
+        self.update();
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        for x in 0..self.width.min(frame.width()) {
+            for y in 0..self.height.min(frame.height()) {
+                let has_ball = self
+                    .balls
+                    .iter()
+                    .any(|ball| (ball.x as usize == x) && (ball.y as usize == y));
+
+                // Tiles are lit for Day; balls render as the inverse of their tile.
+                let on = match self.squares[x][y] {
+                    SquareColor::Day => !has_ball,
+                    SquareColor::Night => has_ball,
+                };
+                frame.set(x, y, on);
+            }
+        }
+    }
+
+    fn resize(&mut self, width: usize, height: usize, _gap_columns: usize) {
+        GameState::resize(self, width, height);
+    }
+
+    fn handle_command(&mut self, command: &SceneCommand) -> bool {
+        match command {
+            SceneCommand::Reset => {
+                let seed = self.rng.gen();
+                *self = GameState::with_options(self.width(), self.height(), self.physics, Some(seed));
+                true
+            }
+        }
+    }
+
+    fn scores(&self) -> Vec<Score> {
+        vec![Score {
+            label: self.name().to_string(),
+            day: self.day_score,
+            night: self.night_score,
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn physics_keeps_defaults_for_missing_keys() {
+        let physics: Physics = "max=0.6, randomness=0".parse().unwrap();
+        assert_eq!(
+            physics,
+            Physics {
+                max_speed: 0.6,
+                speed_randomness: 0.0,
+                ..Physics::default()
+            }
+        );
+        assert_eq!("".parse::<Physics>().unwrap(), Physics::default());
+    }
+
+    #[test]
+    fn physics_rejects_bad_input() {
+        for bad in [
+            "base",
+            "base=fast",
+            "speed=0.3",
+            "base=inf",
+            "min=0",
+            "min=0.4,max=0.3",
+            "max=1.0",
+            "base=0",
+            "randomness=-0.1",
+        ] {
+            assert!(bad.parse::<Physics>().is_err(), "accepted '{}'", bad);
+        }
+    }
+}
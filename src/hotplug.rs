@@ -0,0 +1,56 @@
+use log::warn;
+use serialport::SerialPortInfo;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::led_matrix;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Polls for LED Matrix modules on a background thread and reports when the set of
+/// connected modules changes.
+pub struct DeviceWatcher {
+    pending: Arc<Mutex<Option<Vec<SerialPortInfo>>>>,
+}
+
+impl DeviceWatcher {
+    /// Changes are reported relative to `known`, the port names of the modules the matrix
+    /// was opened with, so modules that come or go while starting up are not missed.
+    pub fn spawn(mut known: Vec<String>) -> Self {
+        let pending = Arc::new(Mutex::new(None));
+        let shared = pending.clone();
+
+        thread::spawn(move || {
+            loop {
+                thread::sleep(POLL_INTERVAL);
+                let modules = match led_matrix::find_modules() {
+                    Ok(modules) => modules,
+                    Err(e) => {
+                        warn!("Failed listing serial ports: {}", e);
+                        continue;
+                    }
+                };
+
+                let names = port_names(&modules);
+                if names != known {
+                    known = names;
+                    if let Ok(mut slot) = shared.lock() {
+                        *slot = Some(modules);
+                    }
+                }
+            }
+        });
+
+        DeviceWatcher { pending }
+    }
+
+    /// Returns the new set of connected modules if it changed since the last call.
+    pub fn take_change(&self) -> Option<Vec<SerialPortInfo>> {
+        self.pending.lock().ok().and_then(|mut slot| slot.take())
+    }
+}
+
+fn port_names(modules: &[SerialPortInfo]) -> Vec<String> {
+    modules.iter().map(|m| m.port_name.clone()).collect()
+}
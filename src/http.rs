@@ -0,0 +1,79 @@
+use anyhow::{anyhow, Result};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+const MAX_BODY: usize = 64 * 1024;
+
+/// The parts of an HTTP/1.1 request the built-in servers look at.
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// Reads one request from `stream`, giving up after a short timeout.
+pub fn read_request(stream: &TcpStream) -> Result<Request> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(anyhow!("Malformed request line '{}'", request_line.trim()));
+    };
+    let path = target.split('?').next().unwrap_or(target).to_string();
+
+    let mut content_type = None;
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-type") {
+            content_type = Some(value.to_string());
+        } else if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse()?;
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err(anyhow!("Request body of {} bytes is too large", content_length));
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Request {
+        method: method.to_string(),
+        path,
+        content_type,
+        body,
+    })
+}
+
+/// Writes a complete response and closes the exchange.
+pub fn respond(mut stream: &TcpStream, status: &str, content_type: &str, body: &str) -> Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+    Ok(())
+}
+
+pub fn not_found(stream: &TcpStream) -> Result<()> {
+    respond(stream, "404 Not Found", "text/plain", "Not found\n")
+}
@@ -0,0 +1,178 @@
+use log::{info, warn};
+
+use crate::control::{Command, Controls};
+
+const BRIGHTNESS_STEP: u8 = 10; // Percent per key press
+const SPEED_STEP: u8 = 4; // Frames per second per key press
+
+pub const HELP: &str =
+    "Keys: space pause/resume, s step, +/- brightness, ]/[ speed, r reset, o overlay, q quit";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Key {
+    TogglePause,
+    Step,
+    BrightnessUp,
+    BrightnessDown,
+    SpeedUp,
+    SpeedDown,
+    Reset,
+    ToggleOverlay,
+    Quit,
+    Help,
+}
+
+fn key_for(byte: u8) -> Option<Key> {
+    match byte {
+        b' ' | b'p' => Some(Key::TogglePause),
+        b's' | b'n' => Some(Key::Step),
+        b'+' | b'=' => Some(Key::BrightnessUp),
+        b'-' | b'_' => Some(Key::BrightnessDown),
+        b']' | b'.' => Some(Key::SpeedUp),
+        b'[' | b',' => Some(Key::SpeedDown),
+        b'r' => Some(Key::Reset),
+        b'o' | b'd' => Some(Key::ToggleOverlay),
+        b'q' => Some(Key::Quit),
+        b'h' | b'?' => Some(Key::Help),
+        _ => None,
+    }
+}
+
+// Arrow keys arrive as `ESC [ A..D`: up/down change the brightness, right/left the speed.
+fn arrow_key(code: u8) -> Option<Key> {
+    match code {
+        b'A' => Some(Key::BrightnessUp),
+        b'B' => Some(Key::BrightnessDown),
+        b'C' => Some(Key::SpeedUp),
+        b'D' => Some(Key::SpeedDown),
+        _ => None,
+    }
+}
+
+fn handle(key: Key, controls: &Controls) {
+    let command = match key {
+        Key::TogglePause => Command::TogglePause,
+        Key::Step => Command::Step,
+        Key::Reset => Command::Reset,
+        Key::ToggleOverlay => Command::ToggleOverlay,
+        Key::Quit => Command::Quit,
+        Key::Help => {
+            println!("{}", HELP);
+            return;
+        }
+        Key::BrightnessUp | Key::BrightnessDown => {
+            let Some(status) = controls.submit(Command::Status).status else {
+                return;
+            };
+            let percent = if key == Key::BrightnessUp {
+                status.brightness.saturating_add(BRIGHTNESS_STEP).min(100)
+            } else {
+                status.brightness.saturating_sub(BRIGHTNESS_STEP)
+            };
+            Command::Brightness { percent }
+        }
+        Key::SpeedUp | Key::SpeedDown => {
+            let Some(status) = controls.submit(Command::Status).status else {
+                return;
+            };
+            let fps = if key == Key::SpeedUp {
+                status.speed.saturating_add(SPEED_STEP).min(64)
+            } else {
+                status.speed.saturating_sub(SPEED_STEP).max(1)
+            };
+            Command::Speed { fps }
+        }
+    };
+
+    let announcement = match command {
+        Command::Brightness { percent } => Some(format!("Brightness {}%", percent)),
+        Command::Speed { fps } => Some(format!("Speed {} fps", fps)),
+        _ => None,
+    };
+    let reply = controls.submit(command);
+    match reply.error {
+        Some(error) => warn!("{}", error),
+        None => {
+            if let Some(announcement) = announcement {
+                info!("{}", announcement);
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use terminal::spawn;
+
+/// Keyboard controls need a Unix terminal; elsewhere only Ctrl+C is available.
+#[cfg(not(unix))]
+pub fn spawn(_controls: Controls) -> Option<()> {
+    None
+}
+
+#[cfg(unix)]
+mod terminal {
+    use super::{arrow_key, handle, key_for};
+    use crate::control::Controls;
+    use std::io::Read;
+    use std::thread;
+
+    /// Restores the terminal settings when dropped.
+    pub struct KeyboardGuard {
+        saved: libc::termios,
+    }
+
+    impl Drop for KeyboardGuard {
+        fn drop(&mut self) {
+            // SAFETY: `saved` holds the settings read from this terminal at startup.
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+            }
+        }
+    }
+
+    /// Reads single key presses from the terminal on a background thread. Returns `None`
+    /// when stdin is not a terminal, e.g. under systemd or with input redirected.
+    ///
+    /// Only line buffering and echo are turned off: output processing and Ctrl+C keep
+    /// working, so log lines still print normally while keys are read.
+    pub fn spawn(controls: Controls) -> Option<KeyboardGuard> {
+        // SAFETY: plain termios calls on stdin; `saved` is fully written by tcgetattr
+        // before it is read.
+        let saved = unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1 {
+                return None;
+            }
+            let mut saved: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return None;
+            }
+            let mut keys = saved;
+            keys.c_lflag &= !(libc::ICANON | libc::ECHO);
+            keys.c_cc[libc::VMIN] = 1;
+            keys.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &keys) != 0 {
+                return None;
+            }
+            saved
+        };
+
+        thread::spawn(move || {
+            let mut bytes = std::io::stdin().lock().bytes().map_while(Result::ok);
+            while let Some(byte) = bytes.next() {
+                let key = if byte == 0x1b {
+                    match (bytes.next(), bytes.next()) {
+                        (Some(b'['), Some(code)) => arrow_key(code),
+                        _ => None,
+                    }
+                } else {
+                    key_for(byte)
+                };
+                if let Some(key) = key {
+                    handle(key, &controls);
+                }
+            }
+        });
+
+        Some(KeyboardGuard { saved })
+    }
+}
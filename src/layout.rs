@@ -0,0 +1,388 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serialport::{SerialPortInfo, SerialPortType};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+pub const MODULE_WIDTH: usize = 9;
+pub const MODULE_HEIGHT: usize = 34;
+
+/// How a module is physically mounted, applied when packing its frame bits.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Orientation {
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+}
+
+impl Orientation {
+    fn combine(self, other: Orientation) -> Orientation {
+        Orientation {
+            flip_horizontal: self.flip_horizontal != other.flip_horizontal,
+            flip_vertical: self.flip_vertical != other.flip_vertical,
+        }
+    }
+}
+
+// Accepts `normal`, `rotate180`, `flip-h` and `flip-v`, combined with `+` (e.g. `flip-h+flip-v`).
+impl FromStr for Orientation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut orientation = Orientation::default();
+        for part in s.split('+').map(str::trim) {
+            let transform = match part {
+                "normal" => Orientation::default(),
+                "rotate180" => Orientation {
+                    flip_horizontal: true,
+                    flip_vertical: true,
+                },
+                "flip-h" => Orientation {
+                    flip_horizontal: true,
+                    flip_vertical: false,
+                },
+                "flip-v" => Orientation {
+                    flip_horizontal: false,
+                    flip_vertical: true,
+                },
+                other => {
+                    return Err(anyhow!(
+                        "Unknown orientation '{}' (expected normal, rotate180, flip-h or flip-v)",
+                        other
+                    ))
+                }
+            };
+            orientation = orientation.combine(transform);
+        }
+        Ok(orientation)
+    }
+}
+
+/// One module of an explicit layout: which serial number goes where on the canvas.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleSpec {
+    pub serial: String,
+    pub x: usize,
+    pub y: usize,
+    /// Falls back to the `--orientation` entry for this serial when not given.
+    pub orientation: Option<Orientation>,
+}
+
+/// An explicit arrangement of any number of modules. The canvas is the bounding box of
+/// all modules, whether or not they are currently connected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layout {
+    pub modules: Vec<ModuleSpec>,
+}
+
+impl Layout {
+    pub fn width(&self) -> usize {
+        self.modules
+            .iter()
+            .map(|m| m.x + MODULE_WIDTH)
+            .max()
+            .unwrap_or(MODULE_WIDTH)
+    }
+
+    pub fn height(&self) -> usize {
+        self.modules
+            .iter()
+            .map(|m| m.y + MODULE_HEIGHT)
+            .max()
+            .unwrap_or(MODULE_HEIGHT)
+    }
+}
+
+// Modules are separated by `;` and written as `SERIAL@X,Y` with an optional `:ORIENTATION`,
+// e.g. `FRAKDEAM1@0,0;FRAKDEAM2@9,0:rotate180`. Offsets are in LEDs.
+impl FromStr for Layout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut modules: Vec<ModuleSpec> = Vec::new();
+        for entry in s.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            let (module, orientation) = match entry.split_once(':') {
+                Some((module, orientation)) => (module, Some(orientation.parse()?)),
+                None => (entry, None),
+            };
+            let (serial, position) = module
+                .split_once('@')
+                .ok_or_else(|| anyhow!("Expected SERIAL@X,Y in layout entry '{}'", entry))?;
+            let (x, y) = position
+                .split_once(',')
+                .ok_or_else(|| anyhow!("Expected X,Y offset in layout entry '{}'", entry))?;
+            let serial = serial.trim().to_string();
+            if serial.is_empty() {
+                return Err(anyhow!("Missing serial number in layout entry '{}'", entry));
+            }
+            if modules.iter().any(|m| m.serial == serial) {
+                return Err(anyhow!("Module {} appears twice in the layout", serial));
+            }
+            modules.push(ModuleSpec {
+                serial,
+                x: x.trim()
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid X offset in '{}': {}", entry, e))?,
+                y: y.trim()
+                    .parse()
+                    .map_err(|e| anyhow!("Invalid Y offset in '{}': {}", entry, e))?,
+                orientation,
+            });
+        }
+
+        if modules.is_empty() {
+            return Err(anyhow!("Layout must contain at least one module"));
+        }
+        Ok(Layout { modules })
+    }
+}
+
+/// How the scene's frame is spread over the connected modules.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutputMode {
+    /// One module.
+    Single,
+    /// Two modules side-by-side forming one double-wide canvas, with `gap_columns` hidden
+    /// columns between them standing in for the physical space between the modules.
+    Dual { gap_columns: usize },
+    /// Every connected module shows the same single-module frame, optionally with every
+    /// second module flipped horizontally for a symmetric look.
+    Mirror { flip_second: bool },
+    /// Modules placed by serial number as described by the layout.
+    Custom(Layout),
+}
+
+/// A detected module together with the part of the canvas it shows.
+pub struct Placement {
+    pub info: SerialPortInfo,
+    pub x: usize,
+    pub y: usize,
+    pub orientation: Orientation,
+}
+
+/// Assigns detected modules (sorted by serial number) to canvas positions for `mode`.
+/// Whatever subset of the wanted modules is present gets used, so the output can shrink
+/// and grow again as modules come and go.
+pub fn plan(
+    mode: &OutputMode,
+    mut modules: Vec<SerialPortInfo>,
+    orientations: &HashMap<String, Orientation>,
+) -> Vec<Placement> {
+    let mounted = |info: &SerialPortInfo| {
+        serial_number(info)
+            .and_then(|serial| orientations.get(serial))
+            .copied()
+            .unwrap_or_default()
+    };
+
+    match mode {
+        OutputMode::Custom(layout) => {
+            let mut placements = Vec::new();
+            for spec in &layout.modules {
+                match modules
+                    .iter()
+                    .position(|info| serial_number(info) == Some(spec.serial.as_str()))
+                {
+                    Some(index) => {
+                        let info = modules.remove(index);
+                        let orientation = spec.orientation.unwrap_or_else(|| mounted(&info));
+                        placements.push(Placement {
+                            info,
+                            x: spec.x,
+                            y: spec.y,
+                            orientation,
+                        });
+                    }
+                    None => warn!("Layout module {} not found; leaving its area dark", spec.serial),
+                }
+            }
+            placements
+        }
+        OutputMode::Single | OutputMode::Dual { .. } => {
+            let (wanted, gap_columns) = match mode {
+                OutputMode::Dual { gap_columns } => (2, *gap_columns),
+                _ => (1, 0),
+            };
+            modules.truncate(wanted);
+            if modules.len() == 2 {
+                modules.reverse();
+                info!(
+                    "Auto-ordered modules: {} = right, {} = left",
+                    modules[0].port_name, modules[1].port_name
+                );
+            }
+            modules
+                .into_iter()
+                .enumerate()
+                .map(|(index, info)| Placement {
+                    orientation: mounted(&info),
+                    info,
+                    x: index * (MODULE_WIDTH + gap_columns),
+                    y: 0,
+                })
+                .collect()
+        }
+        OutputMode::Mirror { flip_second } => modules
+            .into_iter()
+            .enumerate()
+            .map(|(index, info)| Placement {
+                orientation: mounted(&info).combine(Orientation {
+                    flip_horizontal: *flip_second && index % 2 == 1,
+                    flip_vertical: false,
+                }),
+                info,
+                x: 0,
+                y: 0,
+            })
+            .collect(),
+    }
+}
+
+pub fn serial_number(info: &SerialPortInfo) -> Option<&str> {
+    match &info.port_type {
+        SerialPortType::UsbPort(usb) => usb.serial_number.as_deref(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orientation_flips_combine() {
+        let rotate180: Orientation = "rotate180".parse().unwrap();
+        assert_eq!("flip-h+flip-v".parse::<Orientation>().unwrap(), rotate180);
+        assert_eq!("rotate180 + flip-h".parse::<Orientation>().unwrap(), "flip-v".parse().unwrap());
+        assert_eq!("flip-h+flip-h".parse::<Orientation>().unwrap(), Orientation::default());
+        assert_eq!("normal".parse::<Orientation>().unwrap(), Orientation::default());
+    }
+
+    #[test]
+    fn orientation_rejects_unknown_transforms() {
+        assert!("rotate90".parse::<Orientation>().is_err());
+        assert!("flip-h+".parse::<Orientation>().is_err());
+    }
+
+    #[test]
+    fn layout_parses_offsets_and_orientation() {
+        let layout: Layout = "FRAKDEAM1@0,0; FRAKDEAM2@9,4:flip-h+flip-v;".parse().unwrap();
+        assert_eq!(
+            layout.modules,
+            vec![
+                ModuleSpec {
+                    serial: "FRAKDEAM1".to_string(),
+                    x: 0,
+                    y: 0,
+                    orientation: None,
+                },
+                ModuleSpec {
+                    serial: "FRAKDEAM2".to_string(),
+                    x: 9,
+                    y: 4,
+                    orientation: Some("rotate180".parse().unwrap()),
+                },
+            ]
+        );
+        assert_eq!(layout.width(), 9 + MODULE_WIDTH);
+        assert_eq!(layout.height(), 4 + MODULE_HEIGHT);
+    }
+
+    #[test]
+    fn layout_rejects_bad_entries() {
+        for bad in [
+            "",
+            "FRAKDEAM1@0,0;FRAKDEAM1@9,0",
+            "FRAKDEAM1@-1,0",
+            "FRAKDEAM1@0,x",
+            "FRAKDEAM1@9",
+            "FRAKDEAM1",
+            "@0,0",
+            "FRAKDEAM1@0,0:sideways",
+        ] {
+            assert!(bad.parse::<Layout>().is_err(), "accepted '{}'", bad);
+        }
+    }
+
+    fn module(port_name: &str, serial: &str) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: port_name.to_string(),
+            port_type: SerialPortType::UsbPort(serialport::UsbPortInfo {
+                vid: 0x32ac,
+                pid: 0x0020,
+                serial_number: Some(serial.to_string()),
+                manufacturer: None,
+                product: None,
+            }),
+        }
+    }
+
+    /// Two modules as detected, sorted by serial number.
+    fn two_modules() -> Vec<SerialPortInfo> {
+        vec![module("/dev/ttyACM0", "FRAKDEAM1"), module("/dev/ttyACM1", "FRAKDEAM2")]
+    }
+
+    fn positions(placements: &[Placement]) -> Vec<(&str, usize, usize)> {
+        placements
+            .iter()
+            .map(|p| (p.info.port_name.as_str(), p.x, p.y))
+            .collect()
+    }
+
+    #[test]
+    fn dual_mode_orders_modules_and_offsets_them_by_the_gap() {
+        let modules = two_modules();
+        let placements = plan(&OutputMode::Dual { gap_columns: 3 }, modules, &HashMap::new());
+        assert_eq!(
+            positions(&placements),
+            [("/dev/ttyACM1", 0, 0), ("/dev/ttyACM0", MODULE_WIDTH + 3, 0)]
+        );
+    }
+
+    #[test]
+    fn mirror_mode_flips_every_second_module() {
+        let modules = vec![
+            module("/dev/ttyACM0", "FRAKDEAM1"),
+            module("/dev/ttyACM1", "FRAKDEAM2"),
+            module("/dev/ttyACM2", "FRAKDEAM3"),
+        ];
+        let mut orientations = HashMap::new();
+        orientations.insert("FRAKDEAM2".to_string(), "flip-h".parse().unwrap());
+        let placements = plan(&OutputMode::Mirror { flip_second: true }, modules, &orientations);
+        let flips: Vec<bool> = placements.iter().map(|p| p.orientation.flip_horizontal).collect();
+        // The mirror flip cancels out the mounted flip of the second module.
+        assert_eq!(flips, [false, false, false]);
+        assert!(placements.iter().all(|p| (p.x, p.y) == (0, 0)));
+
+        let modules = two_modules();
+        let placements = plan(&OutputMode::Mirror { flip_second: true }, modules, &HashMap::new());
+        let flips: Vec<bool> = placements.iter().map(|p| p.orientation.flip_horizontal).collect();
+        assert_eq!(flips, [false, true]);
+    }
+
+    #[test]
+    fn custom_layout_skips_missing_modules() {
+        let layout: Layout = "FRAKDEAM1@0,0; FRAKDEAM9@9,0; FRAKDEAM2@18,0:flip-v".parse().unwrap();
+        let mut modules = two_modules();
+        modules.reverse();
+        let placements = plan(&OutputMode::Custom(layout), modules, &HashMap::new());
+        assert_eq!(positions(&placements), [("/dev/ttyACM0", 0, 0), ("/dev/ttyACM1", 18, 0)]);
+        assert!(placements[1].orientation.flip_vertical);
+    }
+
+    #[test]
+    fn dual_mode_shrinks_and_grows_with_hotplug() {
+        let mode = OutputMode::Dual { gap_columns: 2 };
+        assert_eq!(plan(&mode, two_modules(), &HashMap::new()).len(), 2);
+
+        let one = plan(&mode, vec![module("/dev/ttyACM1", "FRAKDEAM2")], &HashMap::new());
+        assert_eq!(positions(&one), [("/dev/ttyACM1", 0, 0)]);
+
+        let mut three = two_modules();
+        three.push(module("/dev/ttyACM2", "FRAKDEAM3"));
+        let regrown = plan(&mode, three, &HashMap::new());
+        assert_eq!(
+            positions(&regrown),
+            [("/dev/ttyACM1", 0, 0), ("/dev/ttyACM0", MODULE_WIDTH + 2, 0)]
+        );
+    }
+}
@@ -1,11 +1,17 @@
 use anyhow::{anyhow, Result};
-use serialport::{DataBits, Parity, SerialPort, StopBits};
+use log::{error, info, warn, Level};
+use rand::Rng;
+use serialport::{DataBits, Parity, SerialPort, SerialPortInfo, StopBits};
+use std::collections::HashMap;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicU8, Ordering};
-use std::sync::Arc;
-use std::thread;
-use std::time::Duration;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
-use crate::game::{GameState, SquareColor};
+use crate::frame::Frame;
+use crate::layout::{self, Orientation, OutputMode, Placement, MODULE_HEIGHT, MODULE_WIDTH};
+use crate::logging::rate_limited;
 
 const BAUD_RATE: u32 = 115200;
 const TIMEOUT_MS: u64 = 5000;
@@ -15,36 +21,326 @@ const MAGIC_WORD: [u8; 2] = [0x32, 0xAC];
 
 // Command IDs
 const CMD_BRIGHTNESS: u8 = 0x00;
+const CMD_SLEEP: u8 = 0x03;
+const CMD_ANIMATE: u8 = 0x04;
 const CMD_DRAW_BW: u8 = 0x06;
 const CMD_STAGE_GREY_COL: u8 = 0x07;
 const CMD_DRAW_GREY_BUFFER: u8 = 0x08;
 
 // Pre-calculated buffer sizes
 const COMMIT_CMD_SIZE: usize = 4; // Magic(2) + Cmd(1) + Unused(1)
-const MODULE_WIDTH: usize = 9;
+const BW_FRAME_SIZE: usize = (MODULE_WIDTH * MODULE_HEIGHT).div_ceil(8);
 
 // Flow control constants
-const RECOVERY_DELAY_MS: u64 = 2000; // Delay after error before retry
-const MAX_CONSECUTIVE_ERRORS: u32 = 3; // Max errors before reset attempt
+const RECOVERY_DELAY_MS: u64 = 250; // First retry delay, doubled on every failed attempt
+pub const DEFAULT_MAX_RECOVERY_WAIT: Duration = Duration::from_secs(30); // Cap for the retry delay
+const MAX_CONSECUTIVE_ERRORS: u32 = 3; // Max errors before a port is reopened
+const LATENCY_SMOOTHING: f64 = 0.2; // Weight of the newest sample in the write latency average
+const FRAME_SYNC_WINDOW: Duration = Duration::from_millis(4); // Longest wait for sibling writers
 
+const RESPONSE_SIZE: usize = 32; // Every reply from the firmware is padded to this size
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+const FADE_DURATION: Duration = Duration::from_secs(1);
+const FADE_STEPS: u32 = 20;
+const FALLBACK_BRIGHTNESS_PERCENT: u8 = 50; // The default --brightness, for modules that do not report theirs
+
+pub fn percent_to_led_value(percent: u8) -> u8 {
+    ((percent as u16 * 255) / 100) as u8
+}
+
+pub fn led_value_to_percent(value: u8) -> u8 {
+    ((value as u16 * 100 + 127) / 255) as u8
+}
+
+/// What to leave on the modules when the program exits.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ExitAction {
+    /// Leave the last frame on the display.
+    Keep,
+    /// Turn every LED off.
+    #[default]
+    Blank,
+    /// Fade the brightness to zero over a second, then blank.
+    Fade,
+    /// Blank and put back the brightness the module had before it was opened.
+    Restore,
+    /// Hand the display back to the firmware's built-in animation.
+    Animate,
+    /// Put the module to sleep.
+    Sleep,
+}
+
+impl FromStr for ExitAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "keep" => Ok(ExitAction::Keep),
+            "blank" => Ok(ExitAction::Blank),
+            "fade" => Ok(ExitAction::Fade),
+            "restore" => Ok(ExitAction::Restore),
+            "animate" => Ok(ExitAction::Animate),
+            "sleep" => Ok(ExitAction::Sleep),
+            other => Err(anyhow!(
+                "Unknown exit action '{}' (expected keep, blank, fade, restore, animate or sleep)",
+                other
+            )),
+        }
+    }
+}
+
+/// Health of a single module's connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PortState {
+    Healthy,
+    /// Recent writes failed but the port is still in use.
+    Degraded,
+    /// The port was closed and is being reopened in the background.
+    Recovering,
+}
+
+/// Measured serial throughput across all modules, used to adapt the frame rate.
+#[derive(Clone, Copy, Debug)]
+pub struct Throughput {
+    /// Slowest smoothed write latency of any module that is in use.
+    pub write_latency: Option<Duration>,
+    /// Running totals since the matrix was opened.
+    pub write_errors: u64,
+    pub dropped_frames: u64,
+}
+
+/// Running counters of one module since the matrix was opened, kept across reconnects and
+/// unplugging.
+#[derive(Clone, Debug, Default)]
+pub struct PortStats {
+    pub port_name: String,
+    pub writes: u64,
+    pub bytes_written: u64,
+    pub write_time: Duration,
+    pub max_write_latency: Duration,
+    pub write_errors: u64,
+    pub dropped_frames: u64,
+    pub reconnects: u64,
+}
+
+impl PortStats {
+    pub fn mean_write_latency(&self) -> Option<Duration> {
+        (self.writes > 0).then(|| Duration::from_secs_f64(self.write_time.as_secs_f64() / self.writes as f64))
+    }
+
+    fn add(&mut self, other: &PortStats) {
+        self.writes += other.writes;
+        self.bytes_written += other.bytes_written;
+        self.write_time += other.write_time;
+        self.max_write_latency = self.max_write_latency.max(other.max_write_latency);
+        self.write_errors += other.write_errors;
+        self.dropped_frames += other.dropped_frames;
+        self.reconnects += other.reconnects;
+    }
+}
+
+/// One module's part of a rendered frame, packed as it is sent to the device.
+#[derive(Clone, Debug)]
+pub struct PackedModule {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub orientation: Orientation,
+    /// `CMD_DRAW_BW` payload: one bit per LED, row by row in the module's own orientation.
+    pub bits: Vec<u8>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MirroredFrame {
+    pub generation: u64,
+    pub width: usize,
+    pub height: usize,
+    pub modules: Vec<PackedModule>,
+}
+
+/// Receives a copy of every frame the matrix renders, e.g. for the web preview.
+#[derive(Default)]
+pub struct FrameMirror {
+    frame: Mutex<MirroredFrame>,
+    updated: Condvar,
+}
+
+impl FrameMirror {
+    fn publish(&self, frame: MirroredFrame) {
+        if let Ok(mut current) = self.frame.lock() {
+            *current = frame;
+            self.updated.notify_all();
+        }
+    }
+
+    /// Waits up to `timeout` for a frame newer than `generation`. Frames rendered meanwhile
+    /// are skipped, so a slow reader only ever sees the latest one.
+    pub fn wait_newer(&self, generation: u64, timeout: Duration) -> Option<MirroredFrame> {
+        let current = self.frame.lock().ok()?;
+        let (current, _) = self
+            .updated
+            .wait_timeout_while(current, timeout, |f| f.generation == generation)
+            .ok()?;
+        (current.generation != generation).then(|| current.clone())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PortHealth {
+    pub port_name: String,
+    pub state: PortState,
+    pub consecutive_errors: u32,
+    pub last_error: Option<String>,
+}
+
+// Exponential backoff with jitter: each delay is drawn from the upper half of a window
+// that doubles per attempt, up to `max`.
+struct Backoff {
+    attempt: u32,
+    max: Duration,
+}
+
+impl Backoff {
+    fn next_delay(&mut self) -> Duration {
+        let window = Duration::from_millis(RECOVERY_DELAY_MS)
+            .saturating_mul(1u32 << self.attempt.min(16))
+            .min(self.max);
+        self.attempt += 1;
+        window.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+// Steps of (re)connecting one port, run on its writer thread.
+enum ReconnectStep {
+    Waiting(Duration),
+    Opening,
+    Restoring(Box<dyn SerialPort>),
+}
+
+// Single-slot mailbox between the game loop and a port's writer thread. A new frame replaces
+// one that has not been written yet, so a slow module only ever shows the latest frame.
+#[derive(Default)]
+struct Mailbox {
+    frame: Option<(u64, Vec<u8>)>,
+    brightness: Option<u8>,
+    stop: bool,
+    // Run on the open port before the writer stops.
+    exit: Option<ExitAction>,
+}
+
+struct PortStatus {
+    state: PortState,
+    consecutive_errors: u32,
+    last_error: Option<String>,
+    stats: PortStats,
+    // Smoothed time a write takes to complete, measured on the writer thread.
+    write_latency: Option<Duration>,
+    // Brightness the module reported before it was first opened, if it answered.
+    startup_brightness: Option<u8>,
+}
+
+struct PortShared {
+    mailbox: Mutex<Mailbox>,
+    wake: Condvar,
+    status: Mutex<PortStatus>,
+}
+
+impl PortShared {
+    fn set_status(&self, update: impl FnOnce(&mut PortStatus)) {
+        if let Ok(mut status) = self.status.lock() {
+            update(&mut status);
+        }
+    }
+
+    // Waits up to `timeout` unless asked to stop; returns false once stopped.
+    fn sleep(&self, timeout: Duration) -> bool {
+        let Ok(mailbox) = self.mailbox.lock() else {
+            return false;
+        };
+        match self.wake.wait_timeout_while(mailbox, timeout, |m| !m.stop) {
+            Ok((mailbox, _)) => !mailbox.stop,
+            Err(_) => false,
+        }
+    }
+}
+
+#[derive(Default)]
+struct GateState {
+    generation: u64,
+    expected: usize,
+    arrived: usize,
+}
+
+// Lines up the writer threads of all modules so each frame is sent to every module at the
+// same moment instead of one after the other. A writer that is stuck only holds the others
+// back for `FRAME_SYNC_WINDOW`.
+#[derive(Default)]
+struct FrameGate {
+    state: Mutex<GateState>,
+    released: Condvar,
+}
+
+impl FrameGate {
+    fn open(&self, generation: u64, expected: usize) {
+        if let Ok(mut state) = self.state.lock() {
+            *state = GateState {
+                generation,
+                expected,
+                arrived: 0,
+            };
+            self.released.notify_all();
+        }
+    }
+
+    fn wait(&self, generation: u64) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if state.generation != generation {
+            return;
+        }
+        state.arrived += 1;
+        if state.arrived >= state.expected {
+            self.released.notify_all();
+            return;
+        }
+        let _ = self.released.wait_timeout_while(state, FRAME_SYNC_WINDOW, |s| {
+            s.generation == generation && s.arrived < s.expected
+        });
+    }
+}
+
+// Game-loop side of one module: where it sits on the canvas and the mailbox of its writer.
 struct MatrixPort {
-    port: Box<dyn SerialPort>,
+    port_name: String,
+    shared: Arc<PortShared>,
+    writer: Option<JoinHandle<()>>,
     #[allow(dead_code)]
     column_buffer: Vec<u8>,
     #[allow(dead_code)]
     commit_buffer: [u8; COMMIT_CMD_SIZE],
     width: usize,
+    height: usize,
+    x_offset: usize,
+    y_offset: usize,
+    orientation: Orientation,
     #[allow(dead_code)]
     last_columns: Vec<Vec<u8>>,
 }
 
 impl MatrixPort {
-    #[allow(unused_mut)]
-    fn new(mut port: Box<dyn SerialPort>, height: usize) -> Result<Self> {
-        if let Err(e) = port.clear(serialport::ClearBuffer::All) {
-            return Err(anyhow!("Failed clearing port: {}", e));
-        }
-        thread::sleep(Duration::from_millis(100));
+    /// Starts the writer thread; with no `port` it is opened there in the background.
+    fn spawn(
+        port: Option<Box<dyn SerialPort>>,
+        startup_brightness: Option<u8>,
+        port_name: &str,
+        placement: &Placement,
+        brightness: Arc<AtomicU8>,
+        gate: Arc<FrameGate>,
+        max_wait: Duration,
+    ) -> Self {
+        let height = MODULE_HEIGHT;
 
         let mut column_buffer = Vec::with_capacity(4 + height);
         column_buffer.extend_from_slice(&MAGIC_WORD);
@@ -54,229 +350,850 @@ impl MatrixPort {
 
         let commit_buffer = [MAGIC_WORD[0], MAGIC_WORD[1], CMD_DRAW_GREY_BUFFER, 0x00];
 
-        Ok(MatrixPort {
-            port,
+        let shared = Arc::new(PortShared {
+            mailbox: Mutex::new(Mailbox::default()),
+            wake: Condvar::new(),
+            status: Mutex::new(PortStatus {
+                state: if port.is_some() {
+                    PortState::Healthy
+                } else {
+                    PortState::Recovering
+                },
+                consecutive_errors: 0,
+                last_error: None,
+                stats: PortStats {
+                    port_name: port_name.to_string(),
+                    ..PortStats::default()
+                },
+                write_latency: None,
+                startup_brightness,
+            }),
+        });
+
+        let writer = PortWriter {
+            port_name: port_name.to_string(),
+            shared: shared.clone(),
+            brightness,
+            gate,
+            max_wait,
+        };
+        let writer = thread::spawn(move || writer.run(port));
+
+        MatrixPort {
+            port_name: port_name.to_string(),
+            shared,
+            writer: Some(writer),
             column_buffer,
             commit_buffer,
             width: MODULE_WIDTH,
+            height,
+            x_offset: placement.x,
+            y_offset: placement.y,
+            orientation: placement.orientation,
             last_columns: vec![vec![0xEE; height]; MODULE_WIDTH],
-        })
+        }
+    }
+
+    fn state(&self) -> PortState {
+        self.shared
+            .status
+            .lock()
+            .map(|status| status.state)
+            .unwrap_or(PortState::Recovering)
+    }
+
+    fn health(&self) -> PortHealth {
+        let status = self.shared.status.lock();
+        PortHealth {
+            port_name: self.port_name.clone(),
+            state: status.as_ref().map(|s| s.state).unwrap_or(PortState::Recovering),
+            consecutive_errors: status.as_ref().map(|s| s.consecutive_errors).unwrap_or(0),
+            last_error: status.as_ref().ok().and_then(|s| s.last_error.clone()),
+        }
+    }
+
+    // Never blocks on the device: the writer thread picks the mail up when it is ready.
+    fn post(&self, update: impl FnOnce(&mut Mailbox)) {
+        if let Ok(mut mailbox) = self.shared.mailbox.lock() {
+            update(&mut mailbox);
+            self.shared.wake.notify_all();
+        }
+    }
+}
+
+impl Drop for MatrixPort {
+    fn drop(&mut self) {
+        self.post(|mailbox| mailbox.stop = true);
+    }
+}
+
+impl MatrixPort {
+    /// Asks the writer to run `action` on the port and stop; returns the thread to wait for.
+    fn close(mut self, action: ExitAction) -> Option<JoinHandle<()>> {
+        self.post(|mailbox| {
+            mailbox.exit = Some(action);
+            mailbox.stop = true;
+        });
+        self.writer.take()
+    }
+}
+
+// Owns the serial port of one module and performs all of its blocking I/O.
+struct PortWriter {
+    port_name: String,
+    shared: Arc<PortShared>,
+    brightness: Arc<AtomicU8>,
+    gate: Arc<FrameGate>,
+    max_wait: Duration,
+}
+
+impl PortWriter {
+    fn run(self, mut port: Option<Box<dyn SerialPort>>) {
+        let mut first_connect = port.is_none();
+        loop {
+            let Some(active) = port.as_mut() else {
+                match self.reconnect(first_connect) {
+                    Some(reopened) => {
+                        if first_connect {
+                            info!("Connected LED Matrix on {}", self.port_name);
+                        } else {
+                            info!("Reconnected LED Matrix on {}", self.port_name);
+                        }
+                        let reconnected = !first_connect;
+                        first_connect = false;
+                        port = Some(reopened);
+                        self.shared.set_status(|s| {
+                            s.state = PortState::Healthy;
+                            s.consecutive_errors = 0;
+                            if reconnected {
+                                s.stats.reconnects += 1;
+                            }
+                        });
+                        continue;
+                    }
+                    None => return,
+                }
+            };
+
+            let (brightness, frame) = {
+                let Ok(mailbox) = self.shared.mailbox.lock() else {
+                    return;
+                };
+                let Ok(mut mailbox) = self.shared.wake.wait_while(mailbox, |m| {
+                    !m.stop && m.frame.is_none() && m.brightness.is_none()
+                }) else {
+                    return;
+                };
+                if mailbox.stop {
+                    if let Some(action) = mailbox.exit.take() {
+                        drop(mailbox);
+                        self.finish(active.as_mut(), action);
+                    }
+                    return;
+                }
+                (mailbox.brightness.take(), mailbox.frame.take())
+            };
+
+            let brightness = brightness.map(|b| vec![MAGIC_WORD[0], MAGIC_WORD[1], CMD_BRIGHTNESS, b]);
+            if let Some((generation, _)) = &frame {
+                self.gate.wait(*generation);
+            }
+            let frame = frame.map(|(_, buf)| buf);
+            for buf in brightness.iter().chain(frame.iter()) {
+                if !self.write(active.as_mut(), buf) {
+                    warn!("Closing {} and retrying it in the background", self.port_name);
+                    port = None;
+                    break;
+                }
+            }
+        }
+    }
+
+    fn finish(&self, port: &mut dyn SerialPort, action: ExitAction) {
+        let startup = self.shared.status.lock().ok().and_then(|s| s.startup_brightness);
+        let current = self.brightness.load(Ordering::SeqCst);
+        if let Err(e) = run_exit_action(port, action, current, startup) {
+            error!("{} on {}", e, self.port_name);
+        }
+    }
+
+    // Returns false once the port has failed too often and should be reopened.
+    fn write(&self, port: &mut dyn SerialPort, buf: &[u8]) -> bool {
+        let started = Instant::now();
+        match port.write_all(buf) {
+            Ok(()) => {
+                let latency = started.elapsed();
+                self.shared.set_status(|s| {
+                    if s.consecutive_errors > 0 {
+                        info!("LED Matrix on {} is healthy again", self.port_name);
+                    }
+                    s.state = PortState::Healthy;
+                    s.consecutive_errors = 0;
+                    s.stats.writes += 1;
+                    s.stats.bytes_written += buf.len() as u64;
+                    s.stats.write_time += latency;
+                    s.stats.max_write_latency = s.stats.max_write_latency.max(latency);
+                    s.write_latency = Some(match s.write_latency {
+                        Some(average) => average.mul_f64(1.0 - LATENCY_SMOOTHING) + latency.mul_f64(LATENCY_SMOOTHING),
+                        None => latency,
+                    });
+                });
+                true
+            }
+            Err(e) => {
+                let mut keep = true;
+                self.shared.set_status(|s| {
+                    s.stats.write_errors += 1;
+                    s.consecutive_errors += 1;
+                    s.last_error = Some(e.to_string());
+                    rate_limited!(
+                        Level::Error,
+                        "Write error on {} (#{} consecutive): {}",
+                        self.port_name,
+                        s.consecutive_errors,
+                        e
+                    );
+                    if s.consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
+                        s.state = PortState::Recovering;
+                        keep = false;
+                    } else {
+                        s.state = PortState::Degraded;
+                    }
+                });
+                keep
+            }
+        }
+    }
+
+    // Opens the port with jittered exponential backoff and restores its device state; a port
+    // that never worked is tried right away. Returns `None` if the port was dropped meanwhile.
+    fn reconnect(&self, mut first_connect: bool) -> Option<Box<dyn SerialPort>> {
+        let mut backoff = Backoff {
+            attempt: 0,
+            max: self.max_wait,
+        };
+        let mut step = if first_connect {
+            ReconnectStep::Opening
+        } else {
+            ReconnectStep::Waiting(backoff.next_delay())
+        };
+        loop {
+            step = match step {
+                ReconnectStep::Waiting(delay) => {
+                    if !self.shared.sleep(delay) {
+                        return None;
+                    }
+                    ReconnectStep::Opening
+                }
+                ReconnectStep::Opening => match open_port(&self.port_name) {
+                    Ok(port) => ReconnectStep::Restoring(port),
+                    Err(e) => {
+                        self.shared.set_status(|s| s.last_error = Some(e.to_string()));
+                        ReconnectStep::Waiting(backoff.next_delay())
+                    }
+                },
+                ReconnectStep::Restoring(mut port) => {
+                    // Any brightness change queued meanwhile is covered by the restore.
+                    if let Ok(mut mailbox) = self.shared.mailbox.lock() {
+                        mailbox.brightness = None;
+                    }
+                    if first_connect {
+                        let startup = read_brightness(port.as_mut()).ok();
+                        self.shared.set_status(|s| s.startup_brightness = startup);
+                        first_connect = false;
+                    }
+                    match restore_state(port.as_mut(), self.brightness.load(Ordering::SeqCst)) {
+                        Ok(()) => return Some(port),
+                        Err(e) => {
+                            self.shared.set_status(|s| s.last_error = Some(e.to_string()));
+                            ReconnectStep::Waiting(backoff.next_delay())
+                        }
+                    }
+                }
+            };
+        }
+    }
+}
+
+// Puts a freshly opened module into the state frames expect: awake, with the firmware's own
+// animation stopped and at the current brightness.
+fn restore_state(port: &mut dyn SerialPort, brightness: u8) -> Result<()> {
+    for (command, param) in [(CMD_SLEEP, 0), (CMD_ANIMATE, 0), (CMD_BRIGHTNESS, brightness)] {
+        port.write_all(&[MAGIC_WORD[0], MAGIC_WORD[1], command, param])
+            .map_err(|e| anyhow!("Failed restoring device state: {}", e))?;
     }
+    Ok(())
+}
+
+// Asks the module for its brightness; the reply is the value followed by padding.
+fn read_brightness(port: &mut dyn SerialPort) -> Result<u8> {
+    let timeout = port.timeout();
+    port.set_timeout(RESPONSE_TIMEOUT)?;
+    let mut response = [0u8; RESPONSE_SIZE];
+    let result = port
+        .write_all(&[MAGIC_WORD[0], MAGIC_WORD[1], CMD_BRIGHTNESS])
+        .and_then(|()| port.read_exact(&mut response));
+    port.set_timeout(timeout)?;
+    result.map_err(|e| anyhow!("Failed reading brightness: {}", e))?;
+    Ok(response[0])
+}
+
+fn run_exit_action(
+    port: &mut dyn SerialPort,
+    action: ExitAction,
+    current_brightness: u8,
+    startup_brightness: Option<u8>,
+) -> Result<()> {
+    let command = |port: &mut dyn SerialPort, command: u8, param: u8| {
+        port.write_all(&[MAGIC_WORD[0], MAGIC_WORD[1], command, param])
+    };
+    let blank = |port: &mut dyn SerialPort| {
+        let mut buf = vec![MAGIC_WORD[0], MAGIC_WORD[1], CMD_DRAW_BW];
+        buf.resize(3 + BW_FRAME_SIZE, 0);
+        port.write_all(&buf)
+    };
+
+    let result = match action {
+        ExitAction::Keep => Ok(()),
+        ExitAction::Blank => blank(port),
+        ExitAction::Fade => {
+            for step in (0..FADE_STEPS).rev() {
+                let level = (current_brightness as u32 * step / FADE_STEPS) as u8;
+                command(port, CMD_BRIGHTNESS, level)?;
+                thread::sleep(FADE_DURATION / FADE_STEPS);
+            }
+            // Leave the module dark but at a usable brightness for whatever drives it next.
+            blank(port).and_then(|()| {
+                command(port, CMD_BRIGHTNESS, startup_brightness.unwrap_or(current_brightness))
+            })
+        }
+        ExitAction::Restore => blank(port).and_then(|()| match startup_brightness {
+            Some(brightness) => command(port, CMD_BRIGHTNESS, brightness),
+            None => Ok(()),
+        }),
+        ExitAction::Animate => {
+            let restore = match startup_brightness {
+                Some(brightness) => command(port, CMD_BRIGHTNESS, brightness),
+                None => Ok(()),
+            };
+            restore.and_then(|()| command(port, CMD_ANIMATE, 1))
+        }
+        ExitAction::Sleep => command(port, CMD_SLEEP, 1),
+    };
+    result.map_err(|e| anyhow!("Failed running exit action {:?}: {}", action, e))?;
+    let _ = port.flush();
+    Ok(())
+}
+
+/// Runs `action` on every connected module without starting the game, e.g. to clear a
+/// display left behind by a crashed instance.
+pub fn clear_modules(action: ExitAction) -> Result<()> {
+    let modules = find_modules()?;
+    if modules.is_empty() {
+        return Err(anyhow!("No Framework LED Matrix modules found."));
+    }
+    let mut failed = 0;
+    for info in &modules {
+        let cleared = open_port(&info.port_name).and_then(|mut port| {
+            let current = read_brightness(port.as_mut()).ok();
+            if current.is_none() {
+                warn!(
+                    "Could not read the brightness of {}; assuming {}%",
+                    info.port_name, FALLBACK_BRIGHTNESS_PERCENT
+                );
+            }
+            let brightness = current.unwrap_or_else(|| percent_to_led_value(FALLBACK_BRIGHTNESS_PERCENT));
+            run_exit_action(port.as_mut(), action, brightness, current)
+        });
+        match cleared {
+            Ok(()) => info!("Cleared LED Matrix on {} ({:?})", info.port_name, action),
+            Err(e) => {
+                error!("{}", e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("Failed clearing {} of {} LED Matrix modules", failed, modules.len()));
+    }
+    Ok(())
+}
+
+fn open_port(port_name: &str) -> Result<Box<dyn SerialPort>> {
+    let port = serialport::new(port_name, BAUD_RATE)
+        .timeout(Duration::from_millis(TIMEOUT_MS))
+        .data_bits(DataBits::Eight)
+        .parity(Parity::None)
+        .stop_bits(StopBits::One)
+        .open()
+        .map_err(|e| anyhow!("Failed opening port {}: {}", port_name, e))?;
+    port.clear(serialport::ClearBuffer::All)
+        .map_err(|e| anyhow!("Failed clearing port {}: {}", port_name, e))?;
+    thread::sleep(Duration::from_millis(100));
+    Ok(port)
 }
 
 pub struct LedMatrix {
     ports: Vec<MatrixPort>,
+    mode: OutputMode,
+    orientations: HashMap<String, Orientation>,
     brightness: Arc<AtomicU8>,
-    consecutive_errors: u32,
+    max_recovery_wait: Duration,
+    gate: Arc<FrameGate>,
+    frame_generation: u64,
+    // Counters of ports that were unplugged, so totals never go backwards.
+    retired: Vec<PortStats>,
+    // Every module the output was last planned from, including ones the mode leaves unused.
+    detected: Vec<String>,
+    reported_states: Vec<(String, PortState)>,
+    exit_action: ExitAction,
+    mirror: Option<Arc<FrameMirror>>,
     width: usize,
     height: usize,
 }
 
-impl LedMatrix {
-    pub fn new_with_brightness(brightness: Arc<AtomicU8>, dual_mode: bool, height: usize) -> Result<Self> {
-        let mut candidates: Vec<serialport::SerialPortInfo> = serialport::available_ports()?
-            .into_iter()
-            .filter(|p| matches!(p.port_type, serialport::SerialPortType::UsbPort(ref info) if info.vid == 0x32AC && (info.pid == 0x0020 || info.pid == 0x0021)))
-            .collect();
+/// Lists the connected LED Matrix modules, sorted by serial number.
+pub fn find_modules() -> Result<Vec<SerialPortInfo>> {
+    let mut candidates: Vec<SerialPortInfo> = serialport::available_ports()?
+        .into_iter()
+        .filter(|p| matches!(p.port_type, serialport::SerialPortType::UsbPort(ref info) if info.vid == 0x32AC && (info.pid == 0x0020 || info.pid == 0x0021)))
+        .collect();
 
-        if candidates.is_empty() {
-            return Err(anyhow!("No Framework LED Matrix modules found."));
+    candidates.sort_by(|a, b| {
+        match (layout::serial_number(a), layout::serial_number(b)) {
+            (Some(aa), Some(bb)) => aa.cmp(bb),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (None, None) => a.port_name.cmp(&b.port_name),
         }
+    });
+    Ok(candidates)
+}
 
-        candidates.sort_by(|a, b| {
-            let sa = match &a.port_type {
-                serialport::SerialPortType::UsbPort(info) => info.serial_number.as_deref(),
-                _ => None,
+/// Packs the module whose top-left pixel sits at (`x`, `y`) on the canvas into the bit layout
+/// of the draw command, applying how the module is mounted. Canvas columns outside the module,
+/// such as the hidden gap between two modules, are not sent.
+fn pack_module(frame: &Frame, x: usize, y: usize, orientation: Orientation) -> [u8; BW_FRAME_SIZE] {
+    let mut vals = [0u8; BW_FRAME_SIZE];
+    for local_y in 0..MODULE_HEIGHT.min(frame.height().saturating_sub(y)) {
+        for local_x in 0..MODULE_WIDTH.min(frame.width().saturating_sub(x)) {
+            if !frame.get(x + local_x, y + local_y) {
+                continue;
+            }
+            let column = if orientation.flip_horizontal {
+                MODULE_WIDTH - 1 - local_x
+            } else {
+                local_x
             };
-            let sb = match &b.port_type {
-                serialport::SerialPortType::UsbPort(info) => info.serial_number.as_deref(),
-                _ => None,
+            let row = if orientation.flip_vertical {
+                MODULE_HEIGHT - 1 - local_y
+            } else {
+                local_y
             };
-            match (sa, sb) {
-                (Some(aa), Some(bb)) => aa.cmp(bb),
-                (Some(_), None) => std::cmp::Ordering::Less,
-                (None, Some(_)) => std::cmp::Ordering::Greater,
-                (None, None) => a.port_name.cmp(&b.port_name),
+            let i = column + MODULE_WIDTH * row;
+            vals[i / 8] |= 1u8 << (i % 8);
+        }
+    }
+    vals
+}
+
+impl LedMatrix {
+    /// `orientations` maps module serial numbers to how that module is mounted, and
+    /// `max_recovery_wait` caps the delay between attempts to reopen a failed module. With
+    /// `wait_for_modules` the matrix starts even when no module is usable yet, so modules
+    /// can be added later through `apply_modules`.
+    pub fn new_with_brightness(
+        brightness: Arc<AtomicU8>,
+        mode: OutputMode,
+        orientations: HashMap<String, Orientation>,
+        max_recovery_wait: Duration,
+        wait_for_modules: bool,
+    ) -> Result<Self> {
+        let candidates = find_modules()?;
+
+        if candidates.is_empty() && !wait_for_modules {
+            return Err(anyhow!("No Framework LED Matrix modules found."));
+        }
+        if matches!(mode, OutputMode::Dual { .. }) && candidates.len() < 2 && !wait_for_modules {
+            return Err(anyhow!("Dual mode requested but only {} LED Matrix module detected.", candidates.len()));
+        }
+
+        let mut matrix = LedMatrix {
+            width: MODULE_WIDTH,
+            height: MODULE_HEIGHT,
+            ports: Vec::new(),
+            mode,
+            orientations,
+            brightness,
+            max_recovery_wait,
+            gate: Arc::new(FrameGate::default()),
+            frame_generation: 0,
+            retired: Vec::new(),
+            detected: Vec::new(),
+            reported_states: Vec::new(),
+            exit_action: ExitAction::default(),
+            mirror: None,
+        };
+        matrix.connect(candidates, true);
+
+        if matrix.ports.iter().all(|p| p.state() == PortState::Recovering) {
+            if !wait_for_modules {
+                return Err(anyhow!("Unable to open any Framework LED Matrix modules."));
             }
-        });
+            warn!("No LED Matrix module available yet; waiting for one to be plugged in");
+        }
+        Ok(matrix)
+    }
 
-        let mut desired_ports = if dual_mode {
-            if candidates.len() < 2 {
-                return Err(anyhow!("Dual mode requested but only {} LED Matrix module detected.", candidates.len()));
+    /// Drives exactly the given modules, keeping ports that are already open. New ports are
+    /// opened here when `open_now` is set, otherwise (or if that fails) by their writer thread
+    /// in the background.
+    fn connect(&mut self, modules: Vec<SerialPortInfo>, open_now: bool) {
+        let mut previous = std::mem::take(&mut self.ports);
+        self.detected = modules.iter().map(|m| m.port_name.clone()).collect();
+
+        for placement in layout::plan(&self.mode, modules, &self.orientations) {
+            let info = &placement.info;
+            if let Some(index) = previous.iter().position(|p| p.port_name == info.port_name) {
+                let mut matrix_port = previous.swap_remove(index);
+                matrix_port.x_offset = placement.x;
+                matrix_port.y_offset = placement.y;
+                matrix_port.orientation = placement.orientation;
+                self.ports.push(matrix_port);
+                continue;
             }
-            candidates.truncate(2);
-            candidates
-        } else {
-            candidates.truncate(1);
-            candidates
-        };
 
-        if dual_mode && desired_ports.len() == 2 {
-            desired_ports.reverse();
-            println!(
-                "Auto-ordered modules: {} = right, {} = left",
-                desired_ports[0].port_name, desired_ports[1].port_name
-            );
-        }
-
-        let mut matrix_ports: Vec<MatrixPort> = Vec::new();
-        for info in desired_ports {
-            match serialport::new(&info.port_name, BAUD_RATE)
-                .timeout(Duration::from_millis(TIMEOUT_MS))
-                .data_bits(DataBits::Eight)
-                .parity(Parity::None)
-                .stop_bits(StopBits::One)
-                .open()
-            {
-                Ok(port) => match MatrixPort::new(Box::from(port), height) {
-                    Ok(matrix_port) => {
-                        println!("Connected LED Matrix on {}", info.port_name);
-                        matrix_ports.push(matrix_port);
+            let serial = layout::serial_number(info).unwrap_or("unknown");
+            let (port, startup_brightness) = if open_now {
+                let brightness = self.brightness.load(Ordering::SeqCst);
+                let opened = open_port(&info.port_name).and_then(|mut port| {
+                    let startup = read_brightness(port.as_mut()).ok();
+                    restore_state(port.as_mut(), brightness).map(|()| (port, startup))
+                });
+                match opened {
+                    Ok((port, startup)) => {
+                        info!("Connected LED Matrix on {} (serial {})", info.port_name, serial);
+                        (Some(port), startup)
                     }
-                    Err(e) => eprintln!("Failed initializing port {}: {}", info.port_name, e),
-                },
-                Err(e) => eprintln!("Failed opening port {}: {}", info.port_name, e),
+                    Err(e) => {
+                        warn!("{}; retrying it in the background", e);
+                        (None, None)
+                    }
+                }
+            } else {
+                info!("Found LED Matrix on {} (serial {})", info.port_name, serial);
+                (None, None)
+            };
+            self.ports.push(MatrixPort::spawn(
+                port,
+                startup_brightness,
+                &info.port_name,
+                &placement,
+                self.brightness.clone(),
+                self.gate.clone(),
+                self.max_recovery_wait,
+            ));
+        }
+
+        for gone in previous {
+            info!("LED Matrix on {} disconnected", gone.port_name);
+            if let Ok(status) = gone.shared.status.lock() {
+                self.retired.push(status.stats.clone());
             }
         }
 
-        if matrix_ports.is_empty() {
-            return Err(anyhow!("Unable to open any Framework LED Matrix modules."));
+        // An explicit layout keeps its full size even when some of its modules are missing,
+        // and with nothing connected the last size is kept so the scene can keep running.
+        match &self.mode {
+            OutputMode::Custom(layout) => {
+                self.width = layout.width();
+                self.height = layout.height();
+            }
+            _ if !self.ports.is_empty() => {
+                self.width = self.ports.iter().map(|p| p.x_offset + p.width).max().unwrap_or(MODULE_WIDTH);
+                self.height = self.ports.iter().map(|p| p.y_offset + p.height).max().unwrap_or(MODULE_HEIGHT);
+            }
+            _ => {}
         }
+    }
 
-        Ok(LedMatrix {
-            width: matrix_ports.len() * MODULE_WIDTH,
-            height,
-            ports: matrix_ports,
-            brightness,
-            consecutive_errors: 0,
-        })
+    /// Reshapes the output for a changed set of connected modules, e.g. from a `DeviceWatcher`.
+    /// The canvas size may change; callers should compare `width()`/`height()` afterwards.
+    pub fn apply_modules(&mut self, modules: Vec<SerialPortInfo>) {
+        self.connect(modules, false);
+        info!(
+            "Output is now {}x{} on {} module(s)",
+            self.width,
+            self.height,
+            self.ports.len()
+        );
+    }
+
+    /// Sets what is left on the modules when the matrix is dropped.
+    pub fn set_exit_action(&mut self, action: ExitAction) {
+        self.exit_action = action;
+    }
+
+    /// Publishes every rendered frame to `mirror` as well.
+    pub fn set_mirror(&mut self, mirror: Arc<FrameMirror>) {
+        self.mirror = Some(mirror);
+    }
+
+    /// Port names of the modules the output was last planned from, to seed a `DeviceWatcher`.
+    pub fn detected_ports(&self) -> Vec<String> {
+        self.detected.clone()
+    }
+
+    /// Hidden columns between the two leftmost modules, e.g. `--gap-columns` in dual mode;
+    /// 0 with a single module.
+    pub fn gap_columns(&self) -> usize {
+        let mut modules: Vec<(usize, usize)> =
+            self.ports.iter().map(|p| (p.x_offset, p.width)).collect();
+        modules.sort();
+        match modules[..] {
+            [(x0, width), (x1, _), ..] => x1.saturating_sub(x0 + width),
+            _ => 0,
+        }
     }
 
     pub fn width(&self) -> usize {
         self.width
     }
 
-    pub fn reconnect(&mut self) -> Result<()> {
-        println!("Attempting to reconnect to LED Matrix...");
-        
-        thread::sleep(Duration::from_millis(RECOVERY_DELAY_MS));
-
-        let dual_mode = self.ports.len() > 1;
-        let brightness = self.brightness.clone();
-        let new_self = Self::new_with_brightness(brightness, dual_mode, self.height)?;
+    pub fn height(&self) -> usize {
+        self.height
+    }
 
-        *self = new_self;
+    pub fn port_health(&self) -> Vec<PortHealth> {
+        self.ports.iter().map(MatrixPort::health).collect()
+    }
 
-        println!("Successfully reconnected to LED Matrix");
-        Ok(())
+    /// Returns the health of every port if any port changed state since the last call.
+    pub fn take_health_change(&mut self) -> Option<Vec<PortHealth>> {
+        let states: Vec<(String, PortState)> = self
+            .ports
+            .iter()
+            .map(|p| (p.port_name.clone(), p.state()))
+            .collect();
+        if states == self.reported_states {
+            return None;
+        }
+        self.reported_states = states;
+        Some(self.port_health())
     }
 
+    /// Queues the brightness for every module; modules that are reconnecting pick it up
+    /// once they are back.
     #[inline]
-    pub fn set_brightness(&mut self, brightness: u8) -> Result<()> {
+    pub fn set_brightness(&mut self, brightness: u8) {
         self.brightness.store(brightness, Ordering::SeqCst);
-        for (idx, port) in self.ports.iter_mut().enumerate() {
-            let buf = [MAGIC_WORD[0], MAGIC_WORD[1], CMD_BRIGHTNESS, brightness];
-            port
-                .port
-                .write_all(&buf)
-                .map_err(|e| anyhow!("Failed to set brightness on port {}: {}", idx, e))?;
+        for port in &self.ports {
+            port.post(|mailbox| mailbox.brightness = Some(brightness));
         }
-        Ok(())
     }
 
+    /// Queues the frame for every module without waiting for the devices. A failing module is
+    /// retried in the background while the others keep rendering; an error is only returned
+    /// when no module is usable.
     #[inline]
-    pub fn render(&mut self, game_state: &GameState) -> Result<()> {
-        // Attempt render with error recovery
-        match self.render_internal(game_state) {
-            Ok(()) => {
-                self.consecutive_errors = 0;
-                Ok(())
-            }
-            Err(e) => {
-                self.consecutive_errors += 1;
-                eprintln!(
-                    "Render error (#{} consecutive): {}",
-                    self.consecutive_errors, e
-                );
+    pub fn render(&mut self, frame: &Frame) -> Result<()> {
+        self.render_internal(frame);
+        self.check_any_healthy()
+    }
 
-                // If too many consecutive errors, attempt reconnection
-                if self.consecutive_errors >= MAX_CONSECUTIVE_ERRORS {
-                    eprintln!("Too many consecutive errors, attempting device reset...");
-                    match self.reconnect() {
-                        Ok(()) => {
-                            // Try rendering again after successful reconnection
-                            self.render_internal(game_state)
-                        }
-                        Err(reconnect_err) => {
-                            eprintln!("Failed to reconnect: {}", reconnect_err);
-                            Err(reconnect_err)
-                        }
-                    }
-                } else {
-                    // For occasional errors, just wait a bit and continue
-                    thread::sleep(Duration::from_millis(50));
-                    Err(e)
-                }
-            }
+    fn check_any_healthy(&self) -> Result<()> {
+        if !self.ports.is_empty() && self.ports.iter().all(|p| p.state() == PortState::Recovering) {
+            return Err(anyhow!("No LED Matrix module is responding; retrying in the background"));
         }
+        Ok(())
     }
 
     #[inline]
-    fn render_internal(&mut self, game_state: &GameState) -> Result<()> {
-        for port_index in 0..self.ports.len() {
-            let port = &mut self.ports[port_index];
-
-            let mut vals = [0u8; 39];
-            for y in 0..self.height {
-                if y >= game_state.height() {
-                    break;
-                }
-                for local_x in 0..port.width {
-                    let global_x = port_index * port.width + local_x;
-                    if global_x >= game_state.width() {
-                        break;
-                    }
-
-                    let square_color = game_state.squares[global_x][y];
-                    let has_ball = game_state
-                        .balls
-                        .iter()
-                        .any(|ball| (ball.x as usize == global_x) && (ball.y as usize == y));
-
-                    let on = match square_color {
-                        SquareColor::Day => !has_ball,
-                        SquareColor::Night => has_ball,
-                    };
-
-                    if on {
-                        let i = local_x + MODULE_WIDTH * y;
-                        let byte = i / 8;
-                        let bit = i % 8;
-                        vals[byte] |= 1u8 << bit;
-                    }
-                }
+    fn render_internal(&mut self, frame: &Frame) {
+        // Pack every module first, then release them together through the frame gate. The
+        // mirror also gets modules that are being reconnected.
+        let mut packed = Vec::with_capacity(self.ports.len());
+        let mut mirrored = Vec::new();
+        for port in &self.ports {
+            let recovering = port.state() == PortState::Recovering;
+            if recovering && self.mirror.is_none() {
+                continue;
             }
 
+            let vals = pack_module(frame, port.x_offset, port.y_offset, port.orientation);
+
             let mut buf = Vec::with_capacity(3 + vals.len());
             buf.push(MAGIC_WORD[0]);
             buf.push(MAGIC_WORD[1]);
             buf.push(CMD_DRAW_BW);
             buf.extend_from_slice(&vals);
-            port
-                .port
-                .write_all(&buf)
-                .map_err(|e| anyhow!("Failed to write BW frame on port {}: {}", port_index, e))?;
+            if self.mirror.is_some() {
+                mirrored.push(PackedModule {
+                    x: port.x_offset,
+                    y: port.y_offset,
+                    width: port.width,
+                    height: port.height,
+                    orientation: port.orientation,
+                    bits: vals.to_vec(),
+                });
+            }
+            if !recovering {
+                packed.push((port, buf));
+            }
         }
 
-        Ok(())
+        self.frame_generation = self.frame_generation.wrapping_add(1);
+        let generation = self.frame_generation;
+        if let Some(mirror) = &self.mirror {
+            mirror.publish(MirroredFrame {
+                generation,
+                width: self.width,
+                height: self.height,
+                modules: mirrored,
+            });
+        }
+        self.gate.open(generation, packed.len());
+        for (port, buf) in packed {
+            port.post(|mailbox| {
+                // The writer has not caught up with the previous frame; it is skipped.
+                if mailbox.frame.replace((generation, buf)).is_some() {
+                    port.shared.set_status(|s| s.stats.dropped_frames += 1);
+                }
+            });
+        }
+    }
+
+    pub fn throughput(&self) -> Throughput {
+        let mut throughput = Throughput {
+            write_latency: None,
+            write_errors: self.retired.iter().map(|s| s.write_errors).sum(),
+            dropped_frames: self.retired.iter().map(|s| s.dropped_frames).sum(),
+        };
+        for port in &self.ports {
+            let Ok(status) = port.shared.status.lock() else {
+                continue;
+            };
+            throughput.write_errors += status.stats.write_errors;
+            throughput.dropped_frames += status.stats.dropped_frames;
+            if status.state != PortState::Recovering {
+                throughput.write_latency = throughput.write_latency.max(status.write_latency);
+            }
+        }
+        throughput
+    }
+
+    /// Counters of every module seen since the matrix was opened, including unplugged ones,
+    /// sorted by port name.
+    pub fn port_stats(&self) -> Vec<PortStats> {
+        let live = self
+            .ports
+            .iter()
+            .filter_map(|port| port.shared.status.lock().ok().map(|s| s.stats.clone()));
+        let mut merged: Vec<PortStats> = Vec::new();
+        for stats in self.retired.iter().cloned().chain(live) {
+            match merged.iter_mut().find(|m| m.port_name == stats.port_name) {
+                Some(existing) => existing.add(&stats),
+                None => merged.push(stats),
+            }
+        }
+        merged.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+        merged
+    }
+}
+
+// Runs on every way out of the program that unwinds, including errors returned from `main`.
+impl Drop for LedMatrix {
+    fn drop(&mut self) {
+        // All modules run the action at once, e.g. fade together.
+        let writers: Vec<_> = std::mem::take(&mut self.ports)
+            .into_iter()
+            .filter_map(|port| port.close(self.exit_action))
+            .collect();
+        for writer in writers {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit_bits(vals: &[u8; BW_FRAME_SIZE]) -> Vec<usize> {
+        (0..MODULE_WIDTH * MODULE_HEIGHT).filter(|i| vals[i / 8] & (1 << (i % 8)) != 0).collect()
+    }
+
+    fn orientation(flip_horizontal: bool, flip_vertical: bool) -> Orientation {
+        Orientation {
+            flip_horizontal,
+            flip_vertical,
+        }
+    }
+
+    /// A single module canvas with the pixels (1, 0) and (0, 2) lit.
+    fn frame() -> Frame {
+        let mut frame = Frame::new(MODULE_WIDTH, MODULE_HEIGHT);
+        frame.set(1, 0, true);
+        frame.set(0, 2, true);
+        frame
+    }
+
+    #[test]
+    fn pack_module_places_bits_row_by_row() {
+        let vals = pack_module(&frame(), 0, 0, Orientation::default());
+        assert_eq!(lit_bits(&vals), [1, 2 * MODULE_WIDTH]);
+    }
+
+    #[test]
+    fn pack_module_applies_the_orientation() {
+        let last_row = MODULE_WIDTH * (MODULE_HEIGHT - 1);
+        let flip_h = pack_module(&frame(), 0, 0, orientation(true, false));
+        assert_eq!(lit_bits(&flip_h), [MODULE_WIDTH - 2, 3 * MODULE_WIDTH - 1]);
+
+        let flip_v = pack_module(&frame(), 0, 0, orientation(false, true));
+        assert_eq!(lit_bits(&flip_v), [last_row - 2 * MODULE_WIDTH, last_row + 1]);
+
+        let rotate180 = pack_module(&frame(), 0, 0, "rotate180".parse().unwrap());
+        assert_eq!(
+            lit_bits(&rotate180),
+            [last_row - MODULE_WIDTH - 1, last_row + MODULE_WIDTH - 2]
+        );
+    }
+
+    #[test]
+    fn pack_module_reads_from_its_offset_and_skips_hidden_columns() {
+        // Two modules with a 3 column gap; the gap column and the first column of the right
+        // module are lit.
+        let gap = 3;
+        let mut frame = Frame::new(2 * MODULE_WIDTH + gap, MODULE_HEIGHT);
+        frame.set(MODULE_WIDTH, 0, true);
+        frame.set(MODULE_WIDTH + gap, 1, true);
+
+        assert!(lit_bits(&pack_module(&frame, 0, 0, Orientation::default())).is_empty());
+        let right = pack_module(&frame, MODULE_WIDTH + gap, 0, Orientation::default());
+        assert_eq!(lit_bits(&right), [MODULE_WIDTH]);
+    }
+
+    #[test]
+    fn backoff_delays_fill_the_upper_half_of_a_doubling_window() {
+        let max = Duration::from_secs(5);
+        let mut backoff = Backoff { attempt: 0, max };
+        for attempt in 0..40 {
+            let window = Duration::from_millis(RECOVERY_DELAY_MS)
+                .saturating_mul(1 << attempt.min(16))
+                .min(max);
+            let delay = backoff.next_delay();
+            assert!(delay >= window / 2 && delay <= window, "attempt {}: {:?}", attempt, delay);
+        }
+        assert_eq!(backoff.attempt, 40);
     }
 
-    pub fn estimated_max_fps(&self) -> u32 {
-        // Using DrawBW (0x06): 2 magic + 1 cmd + 39 payload = 42 bytes per port per frame
-        let per_port = 2 + 1 + 39;
-        let total = self.ports.len() * per_port;
-        let bytes_per_sec = (BAUD_RATE as f64) / 10.0;
-        let fps = (bytes_per_sec / ((total as f64) * 1.1)).floor() as u32;
-        if fps < 1 { 1 } else { fps }
+    #[test]
+    fn backoff_never_waits_longer_than_max() {
+        let max = Duration::from_millis(RECOVERY_DELAY_MS * 3);
+        let mut backoff = Backoff { attempt: 0, max };
+        let delays: Vec<Duration> = (0..100).map(|_| backoff.next_delay()).collect();
+        assert!(delays.iter().all(|&delay| delay <= max));
+        assert!(delays[2..].iter().all(|&delay| delay >= max / 2));
     }
 }
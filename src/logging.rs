@@ -0,0 +1,419 @@
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const IDENTIFIER: &str = "fw16-pong-wars";
+const CRATE_TARGET: &str = "fw16_pong_wars";
+
+// Each `rate_limited!` statement may fire this often per window; the rest are counted and
+// reported with the next message that gets through, so per-frame errors cannot flood the
+// output.
+const RATE_LIMIT_BURST: u32 = 5;
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10);
+// Appended to the module's target by `rate_limited!`, so `RUST_LOG` still matches the module.
+const RATE_LIMITED_SUFFIX: &str = "::rate_limited";
+
+/// Like `log!`, but for statements that can fire on every frame: each one is shown at most a
+/// few times every ten seconds, e.g. `rate_limited!(Level::Error, "Render error: {}", e)`.
+macro_rules! rate_limited {
+    ($level:expr, $($arg:tt)+) => {
+        log::log!(target: concat!(module_path!(), "::rate_limited"), $level, $($arg)+)
+    };
+}
+pub(crate) use rate_limited;
+
+/// Installs the logger: plain lines on the terminal, or the systemd journal when running as
+/// a daemon. Levels come from `RUST_LOG` (e.g. `info,led_matrix=debug,stats=debug`), with
+/// `debug` raising the default level to debug.
+pub fn init(daemon: bool, debug: bool) {
+    let default = if debug {
+        LevelFilter::Debug
+    } else {
+        LevelFilter::Info
+    };
+    let filter = Filter::parse(&std::env::var("RUST_LOG").unwrap_or_default(), default);
+    let sink: Box<dyn Log> = if daemon {
+        journal_logger()
+    } else {
+        Box::new(ConsoleLogger)
+    };
+
+    let max_level = filter.max_level();
+    let logger = Logger {
+        filter,
+        sink,
+        limits: Mutex::new(HashMap::new()),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+// `RUST_LOG`-style directives: a bare level sets the default, `target=level` sets the level
+// for a target and everything below it. Targets may leave out the crate name, so
+// `led_matrix=debug` means `fw16_pong_wars::led_matrix=debug`.
+struct Filter {
+    default: LevelFilter,
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl Filter {
+    fn parse(spec: &str, default: LevelFilter) -> Self {
+        let mut filter = Filter {
+            default,
+            directives: Vec::new(),
+        };
+        for directive in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => match level.trim().parse() {
+                    Ok(level) => filter.directives.push((target.trim().to_string(), level)),
+                    Err(_) => eprintln!("Ignoring invalid RUST_LOG directive '{}'", directive),
+                },
+                None => match directive.parse() {
+                    Ok(level) => filter.default = level,
+                    // A bare target enables everything it logs.
+                    Err(_) => filter
+                        .directives
+                        .push((directive.to_string(), LevelFilter::Trace)),
+                },
+            }
+        }
+        // The most specific directive wins.
+        filter.directives.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+        filter
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        let matches = |name: &str, target: &str| {
+            target == name
+                || target
+                    .strip_prefix(name)
+                    .is_some_and(|rest| rest.starts_with("::"))
+        };
+        let local = target
+            .strip_prefix(CRATE_TARGET)
+            .and_then(|rest| rest.strip_prefix("::"));
+        self.directives
+            .iter()
+            .find(|(name, _)| matches(name, target) || local.is_some_and(|l| matches(name, l)))
+            .map_or(self.default, |(_, level)| *level)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.directives
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, std::cmp::max)
+    }
+}
+
+struct RateWindow {
+    started: Instant,
+    count: u32,
+    suppressed: u64,
+}
+
+struct Logger {
+    filter: Filter,
+    sink: Box<dyn Log>,
+    // Keyed by the source location of the log statement.
+    limits: Mutex<HashMap<(String, u32), RateWindow>>,
+}
+
+impl Logger {
+    // Returns how many messages from this statement were dropped since the last one that got
+    // through, or `None` if this one should be dropped as well.
+    fn admit(&self, record: &Record) -> Option<u64> {
+        let (Some(file), Some(line)) = (record.file(), record.line()) else {
+            return Some(0);
+        };
+        let Ok(mut limits) = self.limits.lock() else {
+            return Some(0);
+        };
+        let now = Instant::now();
+        let window = limits
+            .entry((file.to_string(), line))
+            .or_insert(RateWindow {
+                started: now,
+                count: 0,
+                suppressed: 0,
+            });
+        if now.duration_since(window.started) >= RATE_LIMIT_WINDOW {
+            window.started = now;
+            window.count = 0;
+        }
+        if window.count >= RATE_LIMIT_BURST {
+            window.suppressed += 1;
+            return None;
+        }
+        window.count += 1;
+        Some(std::mem::take(&mut window.suppressed))
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level_for(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let Some(target) = record.target().strip_suffix(RATE_LIMITED_SUFFIX) else {
+            self.sink.log(record);
+            return;
+        };
+        let Some(suppressed) = self.admit(record) else {
+            return;
+        };
+        let note = match suppressed {
+            0 => String::new(),
+            n => format!(" ({} similar messages suppressed)", n),
+        };
+        self.sink.log(
+            &Record::builder()
+                .args(format_args!("{}{}", record.args(), note))
+                .level(record.level())
+                .target(target)
+                .file(record.file())
+                .line(record.line())
+                .module_path(record.module_path())
+                .build(),
+        );
+    }
+
+    fn flush(&self) {
+        self.sink.flush();
+    }
+}
+
+// Informational messages go to stdout and problems to stderr, like before logging existed.
+struct ConsoleLogger;
+
+impl Log for ConsoleLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        match record.level() {
+            Level::Error | Level::Warn => eprintln!("{}", record.args()),
+            Level::Info => println!("{}", record.args()),
+            Level::Debug | Level::Trace => {
+                println!("[{}] {}", short_target(record.target()), record.args())
+            }
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stdout().flush();
+    }
+}
+
+fn short_target(target: &str) -> &str {
+    target
+        .strip_prefix(CRATE_TARGET)
+        .map(|rest| rest.trim_start_matches("::"))
+        .filter(|rest| !rest.is_empty())
+        .unwrap_or(target)
+}
+
+#[cfg(target_os = "linux")]
+fn journal_logger() -> Box<dyn Log> {
+    Box::new(journal::JournalLogger::new())
+}
+
+// Without a journal, a daemon still reports problems on stderr.
+#[cfg(not(target_os = "linux"))]
+fn journal_logger() -> Box<dyn Log> {
+    Box::new(QuietLogger)
+}
+
+#[cfg(not(target_os = "linux"))]
+struct QuietLogger;
+
+#[cfg(not(target_os = "linux"))]
+impl Log for QuietLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}", record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(target_os = "linux")]
+mod journal {
+    use super::IDENTIFIER;
+    use log::{Level, Log, Metadata, Record};
+    use std::os::unix::net::UnixDatagram;
+
+    const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+    /// Sends every record to the journal as structured fields using its native protocol,
+    /// falling back to stderr if the journal cannot be reached.
+    pub struct JournalLogger {
+        socket: Option<UnixDatagram>,
+    }
+
+    impl JournalLogger {
+        pub fn new() -> Self {
+            JournalLogger {
+                socket: UnixDatagram::unbound().ok(),
+            }
+        }
+    }
+
+    // Values containing newlines use the binary form: name, newline, little-endian length,
+    // value, newline.
+    fn append_field(buf: &mut Vec<u8>, name: &str, value: &str) {
+        buf.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            buf.push(b'\n');
+            buf.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            buf.push(b'=');
+        }
+        buf.extend_from_slice(value.as_bytes());
+        buf.push(b'\n');
+    }
+
+    fn priority(level: Level) -> &'static str {
+        match level {
+            Level::Error => "3",
+            Level::Warn => "4",
+            Level::Info => "6",
+            Level::Debug | Level::Trace => "7",
+        }
+    }
+
+    impl Log for JournalLogger {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            let message = record.args().to_string();
+            let mut buf = Vec::with_capacity(message.len() + 128);
+            append_field(&mut buf, "MESSAGE", &message);
+            append_field(&mut buf, "PRIORITY", priority(record.level()));
+            append_field(&mut buf, "SYSLOG_IDENTIFIER", IDENTIFIER);
+            append_field(&mut buf, "TARGET", record.target());
+            if let Some(file) = record.file() {
+                append_field(&mut buf, "CODE_FILE", file);
+            }
+            if let Some(line) = record.line() {
+                append_field(&mut buf, "CODE_LINE", &line.to_string());
+            }
+
+            let sent = self
+                .socket
+                .as_ref()
+                .is_some_and(|socket| socket.send_to(&buf, JOURNAL_SOCKET).is_ok());
+            if !sent {
+                eprintln!("<{}>{}", priority(record.level()), message);
+            }
+        }
+
+        fn flush(&self) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn filter_matches_targets_with_or_without_crate_name() {
+        let filter = Filter::parse(
+            "warn, led_matrix=debug,fw16_pong_wars::stats=trace,serialport",
+            LevelFilter::Info,
+        );
+        assert_eq!(filter.level_for("fw16_pong_wars"), LevelFilter::Warn);
+        assert_eq!(filter.level_for("fw16_pong_wars::led_matrix"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("fw16_pong_wars::led_matrix::rate_limited"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("fw16_pong_wars::led_matrix_extra"), LevelFilter::Warn);
+        assert_eq!(filter.level_for("fw16_pong_wars::stats"), LevelFilter::Trace);
+        assert_eq!(filter.level_for("serialport::posix"), LevelFilter::Trace);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn filter_prefers_the_most_specific_directive() {
+        let filter = Filter::parse("game=debug,game::physics=error", LevelFilter::Info);
+        assert_eq!(filter.level_for("fw16_pong_wars::game"), LevelFilter::Debug);
+        assert_eq!(filter.level_for("fw16_pong_wars::game::physics"), LevelFilter::Error);
+    }
+
+    #[test]
+    fn filter_ignores_invalid_levels() {
+        let filter = Filter::parse("led_matrix=loud,", LevelFilter::Debug);
+        assert_eq!(filter.level_for("fw16_pong_wars::led_matrix"), LevelFilter::Debug);
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+    }
+
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<(String, String)>>>);
+
+    impl Log for Capture {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((record.target().to_string(), record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn log_at(logger: &Logger, target: &str, line: u32, message: &str) {
+        logger.log(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(Level::Error)
+                .target(target)
+                .file(Some("src/main.rs"))
+                .line(Some(line))
+                .build(),
+        );
+    }
+
+    #[test]
+    fn only_rate_limited_statements_are_limited() {
+        let capture = Capture::default();
+        let logger = Logger {
+            filter: Filter::parse("", LevelFilter::Info),
+            sink: Box::new(capture.clone()),
+            limits: Mutex::new(HashMap::new()),
+        };
+        for _ in 0..8 {
+            log_at(&logger, "fw16_pong_wars::rate_limited", 1, "Render error");
+            log_at(&logger, "fw16_pong_wars", 2, "Other error");
+        }
+        {
+            let mut window = logger.limits.lock().unwrap();
+            window.get_mut(&("src/main.rs".to_string(), 1)).unwrap().started -= RATE_LIMIT_WINDOW;
+        }
+        log_at(&logger, "fw16_pong_wars::rate_limited", 1, "Render error");
+
+        let logged = capture.0.lock().unwrap();
+        let limited: Vec<_> = logged.iter().filter(|(_, m)| m.starts_with("Render")).collect();
+        assert_eq!(limited.len(), RATE_LIMIT_BURST as usize + 1);
+        assert!(limited.iter().all(|(target, _)| target == "fw16_pong_wars"));
+        assert_eq!(limited.last().unwrap().1, "Render error (3 similar messages suppressed)");
+        assert_eq!(logged.iter().filter(|(_, m)| m == "Other error").count(), 8);
+    }
+}
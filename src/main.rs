@@ -1,23 +1,76 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{anyhow, Result};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, FromArgMatches, Parser};
+use log::{debug, info, warn, Level};
 use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+mod ambient;
+mod config;
+mod control;
+mod frame;
 mod game;
+mod hotplug;
+mod http;
+mod keyboard;
+mod layout;
 mod led_matrix;
+mod logging;
+mod metrics;
+mod pacing;
+mod power;
+mod scene;
+#[cfg(unix)]
+mod signals;
+mod stats;
+mod systemd;
+mod web;
 
-use game::{GameState, DEFAULT_GRID_HEIGHT};
-use led_matrix::LedMatrix;
+use ambient::{BrightnessCurve, DEFAULT_BRIGHTNESS_CURVE};
+use config::Config;
+use control::{Command, Controls, Reply, Request, Status};
+use frame::Frame;
+use game::{GameState, Physics};
+use hotplug::DeviceWatcher;
+use layout::{Layout, Orientation, OutputMode};
+use led_matrix::{
+    led_value_to_percent, ExitAction, FrameMirror, percent_to_led_value, LedMatrix, PortHealth, PortState, DEFAULT_MAX_RECOVERY_WAIT,
+};
+use logging::rate_limited;
+use metrics::{Metrics, SharedMetrics};
+use pacing::{FrameRateController, Pacer, PacingMode, RateChange};
+use power::{PowerLimits, PowerMonitor, PowerProfile, PowerSource, DEFAULT_POWER_SUPPLY_ROOT};
+use scene::{score_line, split_widths, Scene, SceneCommand, Split};
+use stats::FrameStats;
+use systemd::Notifier;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about = "Framework Laptop 16 Pong Wars", long_about = None)]
+#[command(group = clap::ArgGroup::new("multi_module").args(["dual_mode", "layout"]))]
 struct Args {
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+
+    /// Settings file whose values apply to every option not given on the command line
+    /// (defaults to $XDG_CONFIG_HOME/fw16-pong-wars/config.toml)
+    #[arg(long = "config", value_name = "PATH", global = true)]
+    config: Option<PathBuf>,
+
+    /// Apply the [profiles.NAME] section of the settings file on top of its other settings
+    #[arg(long = "profile", value_name = "NAME", global = true)]
+    profile: Option<String>,
+
     /// Enable dual LED matrix mode (requires two modules installed)
     #[arg(short = 'd', long = "dualmode")]
     dual_mode: bool,
 
-    /// Frames per second target (1-64 fps)
+    /// Frames per second target (1-64 fps); the upper limit when the rate adapts
     #[arg(short = 's', long = "speed", default_value_t = 64, value_parser = clap::value_parser!(u8).range(1..=64))]
     speed: u8,
 
@@ -25,122 +78,920 @@ struct Args {
     #[arg(short = 'b', long = "brightness", default_value_t = 50, value_parser = clap::value_parser!(u8).range(0..=100))]
     brightness: u8,
 
-    /// Enable additional debug logging
+    /// Set the brightness from the ambient light sensor instead of --brightness
+    #[arg(long = "auto-brightness")]
+    auto_brightness: bool,
+
+    /// Illuminance file to read (defaults to the first in_illuminance_raw under
+    /// /sys/bus/iio/devices)
+    #[arg(long = "als-path", value_name = "FILE", requires = "auto_brightness")]
+    als_path: Option<PathBuf>,
+
+    /// Ambient light to brightness mapping as LUX:PERCENT points, interpolated in between
+    #[arg(long = "brightness-curve", value_name = "CURVE", default_value = DEFAULT_BRIGHTNESS_CURVE, requires = "auto_brightness")]
+    brightness_curve: BrightnessCurve,
+
+    /// How far (in percent) the curve must move before the brightness follows, so it does
+    /// not flicker with small changes in light
+    #[arg(long = "brightness-hysteresis", value_name = "PERCENT", default_value_t = 5, requires = "auto_brightness", value_parser = clap::value_parser!(u8).range(0..=100))]
+    brightness_hysteresis: u8,
+
+    /// Hidden columns between the two modules in dual mode, so the ball crosses the
+    /// physical gap at a realistic pace
+    #[arg(long = "gap-columns", value_name = "N", default_value_t = 0, requires = "dual_mode")]
+    gap_columns: usize,
+
+    /// Show the same single-module game on every connected module
+    #[arg(long = "mirror", conflicts_with = "dual_mode")]
+    mirror: bool,
+
+    /// In mirror mode, flip every second module horizontally
+    #[arg(long = "mirror-flip", requires = "mirror")]
+    mirror_flip: bool,
+
+    /// Orientation of a module keyed by serial number, e.g. "FRAKDEAM1234=rotate180"
+    /// (normal, rotate180, flip-h, flip-v; combine with +). May be repeated.
+    #[arg(long = "orientation", value_name = "SERIAL=ORIENTATION", value_parser = parse_orientation)]
+    orientations: Vec<(String, Orientation)>,
+
+    /// Explicit module layout, e.g. "SERIAL_A@0,0;SERIAL_B@9,0:rotate180" (offsets in LEDs).
+    /// The game grid covers the whole layout.
+    #[arg(long = "layout", value_name = "LAYOUT", conflicts_with_all = ["dual_mode", "mirror"])]
+    layout: Option<Layout>,
+
+    /// Run an independent game on each half of the canvas (requires --dualmode or --layout)
+    #[arg(long = "split", requires = "multi_module")]
+    split: bool,
+
+    /// Random seed for the game (the left game in split mode)
+    #[arg(long = "seed")]
+    seed: Option<u64>,
+
+    /// Random seed for the right game in split mode (defaults to --seed + 1)
+    #[arg(long = "right-seed", requires = "split")]
+    right_seed: Option<u64>,
+
+    /// Ball physics as key=value pairs, e.g. "base=0.3,min=0.2,max=0.5,randomness=0.001"
+    #[arg(long = "physics")]
+    physics: Option<Physics>,
+
+    /// Ball physics for the right game in split mode (defaults to --physics)
+    #[arg(long = "right-physics", requires = "split")]
+    right_physics: Option<Physics>,
+
+    /// Longest wait in seconds between attempts to reopen a module that stopped responding
+    #[arg(long = "reconnect-max-wait", value_name = "SECONDS", default_value_t = DEFAULT_MAX_RECOVERY_WAIT.as_secs_f64(), value_parser = parse_positive_secs)]
+    reconnect_max_wait: f64,
+
+    /// Always run at --speed instead of adapting the frame rate to measured serial throughput
+    #[arg(long = "fixed-fps")]
+    fixed_fps: bool,
+
+    /// Busy-wait for the last millisecond of every frame for the most even frame timing,
+    /// at the cost of noticeably more CPU and battery
+    #[arg(long = "precise-timing")]
+    precise_timing: bool,
+
+    /// Lower the frame rate and brightness while running on battery, and pause when the
+    /// battery gets low
+    #[arg(long = "battery-saver")]
+    battery_saver: bool,
+
+    /// Frame rate cap on battery
+    #[arg(long = "battery-fps", value_name = "FPS", default_value_t = 30, requires = "battery_saver", value_parser = clap::value_parser!(u8).range(1..=64))]
+    battery_fps: u8,
+
+    /// Brightness cap on battery (0-100)
+    #[arg(long = "battery-brightness", value_name = "PERCENT", default_value_t = 30, requires = "battery_saver", value_parser = clap::value_parser!(u8).range(0..=100))]
+    battery_brightness: u8,
+
+    /// Pause the game at or below this battery charge (0 never pauses)
+    #[arg(long = "battery-pause-below", value_name = "PERCENT", default_value_t = 15, requires = "battery_saver", value_parser = clap::value_parser!(u8).range(0..=100))]
+    battery_pause_below: u8,
+
+    /// Where to read the battery and AC adapter state from
+    #[arg(long = "power-supply-root", value_name = "DIR", default_value = DEFAULT_POWER_SUPPLY_ROOT, requires = "battery_saver")]
+    power_supply_root: PathBuf,
+
+    /// What to leave on the modules when exiting: keep (last frame), blank, fade, restore
+    /// (blank at the brightness the module had before), animate (firmware animation) or sleep
+    #[arg(long = "on-exit", value_name = "ACTION", default_value = "blank")]
+    on_exit: ExitAction,
+
+    /// Path of the control socket used by `ctl` (defaults to
+    /// $XDG_RUNTIME_DIR/fw16-pong-wars.sock)
+    #[arg(long = "control-socket", value_name = "PATH", global = true)]
+    control_socket: Option<PathBuf>,
+
+    /// Do not listen for `ctl` commands
+    #[arg(long = "no-control-socket")]
+    no_control_socket: bool,
+
+    /// Run as a background service: log to the systemd journal instead of the terminal,
+    /// notify systemd when ready and feed its watchdog, and wait for modules to be plugged
+    /// in instead of exiting when none are found
+    #[arg(long = "daemon")]
+    daemon: bool,
+
+    /// Log debug messages. RUST_LOG filters such as "info,led_matrix=debug" select levels
+    /// per target
     #[arg(long = "debug")]
     debug: bool,
+
+    /// Log a frame timing and serial statistics summary every this many seconds (0 only
+    /// logs it at exit)
+    #[arg(long = "stats-interval", value_name = "SECONDS", default_value_t = 60)]
+    stats_interval: u64,
+
+    /// Write the final statistics summary to this file as JSON
+    #[arg(long = "stats-file", value_name = "PATH")]
+    stats_file: Option<PathBuf>,
+
+    /// Serve Prometheus metrics over HTTP on this address, e.g. 127.0.0.1:9716
+    #[arg(long = "metrics-listen", value_name = "ADDR")]
+    metrics_listen: Option<SocketAddr>,
+
+    /// Serve a dashboard with a live preview and controls on this address, e.g.
+    /// 127.0.0.1:8716. Anyone who can reach it can control the game
+    #[arg(long = "web-listen", value_name = "ADDR")]
+    web_listen: Option<SocketAddr>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CliCommand {
+    /// Control a running instance over its control socket
+    Ctl {
+        #[command(subcommand)]
+        action: CtlAction,
+    },
+    /// Blank the connected modules (or run another exit action) without starting the game
+    Clear {
+        /// keep, blank, fade, restore, animate or sleep
+        #[arg(default_value = "blank")]
+        action: ExitAction,
+    },
+    /// Work with the settings file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum ConfigAction {
+    /// Validate the settings file and every profile in it, and print the options they set
+    Check,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum CtlAction {
+    /// Print the scene, frame rate, brightness, module health and scores
+    Status,
+    /// Set the brightness percentage (0-100)
+    Brightness {
+        #[arg(value_parser = clap::value_parser!(u8).range(0..=100))]
+        percent: u8,
+    },
+    /// Set the target frame rate (1-64 fps)
+    Speed {
+        #[arg(value_parser = clap::value_parser!(u8).range(1..=64))]
+        fps: u8,
+    },
+    /// Freeze the game on the current frame
+    Pause,
+    /// Continue a paused game
+    Resume,
+    /// Start the game over
+    Reset,
+    /// Switch to another scene (pong-wars or split)
+    Scene { name: String },
+    /// Stop the running instance
+    Quit,
 }
 
-fn percent_to_led_value(percent: u8) -> u8 {
-    ((percent as u16 * 255) / 100) as u8
+impl From<&CtlAction> for Command {
+    fn from(action: &CtlAction) -> Self {
+        match action {
+            CtlAction::Status => Command::Status,
+            CtlAction::Brightness { percent } => Command::Brightness { percent: *percent },
+            CtlAction::Speed { fps } => Command::Speed { fps: *fps },
+            CtlAction::Pause => Command::Pause,
+            CtlAction::Resume => Command::Resume,
+            CtlAction::Reset => Command::Reset,
+            CtlAction::Scene { name } => Command::Scene { name: name.clone() },
+            CtlAction::Quit => Command::Quit,
+        }
+    }
+}
+
+fn parse_orientation(s: &str) -> Result<(String, Orientation)> {
+    let (serial, orientation) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("Expected SERIAL=ORIENTATION, got '{}'", s))?;
+    Ok((serial.trim().to_string(), orientation.parse()?))
+}
+
+fn parse_positive_secs(s: &str) -> Result<f64> {
+    let secs: f64 = s.parse()?;
+    if !(secs.is_finite() && secs > 0.0) {
+        return Err(anyhow!("Expected a positive number of seconds"));
+    }
+    Ok(secs)
+}
+
+// Finds the settings file: `--config` must exist, the default location may not.
+fn load_config(args: &Args) -> Result<Option<Config>> {
+    let config = match (&args.config, config::default_path()) {
+        (Some(path), _) => Some(Config::load(path)?),
+        (None, Some(path)) if path.exists() => Some(Config::load(&path)?),
+        _ => None,
+    };
+    if config.is_none() && args.profile.is_some() {
+        return Err(anyhow!("--profile needs a settings file, but none was found"));
+    }
+    Ok(config)
+}
+
+// Options are resolved as command line, then the chosen profile, then the rest of the settings
+// file, then the built-in defaults. Returns the settings file that was used, if any.
+fn load_args(argv: &[OsString]) -> Result<(Args, Option<PathBuf>)> {
+    let mut command = cli_command();
+    // Options the command line needs may come from the settings file, so `requires` is only
+    // checked once the file is merged in.
+    let cli = config::without_requirements(&command).get_matches_from(argv);
+    let args = Args::from_arg_matches(&cli)?;
+    if let Some((name, _)) = cli.subcommand() {
+        // Subcommands only use the global options; anything else would be silently ignored.
+        let ignored = command.get_arguments().find(|arg| {
+            !arg.is_global_set() && cli.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+        });
+        if let Some(arg) = ignored {
+            let message = format!(
+                "the subcommand '{}' cannot be used with '--{}'",
+                name,
+                arg.get_long().unwrap_or(arg.get_id().as_str())
+            );
+            command.error(ErrorKind::ArgumentConflict, message).exit();
+        }
+        return Ok((args, None));
+    }
+    let Some(config) = load_config(&args)? else {
+        let args = Args::from_arg_matches(&command.get_matches_from(argv))?;
+        return Ok((args, None));
+    };
+
+    let settings = config.to_args(args.profile.as_deref(), &command, &cli)?;
+    let full = argv
+        .iter()
+        .take(1)
+        .cloned()
+        .chain(settings.into_iter().map(OsString::from))
+        .chain(argv.iter().skip(1).cloned());
+    let args = parse_args(&command, full).map_err(|e| {
+        anyhow!(
+            "{} (check {} with `fw16-pong-wars config check`)",
+            first_line(&e),
+            config.path().display()
+        )
+    })?;
+    Ok((args, Some(config.path().to_path_buf())))
+}
+
+// The options with a `--no-` form for every on/off option, so the command line can turn off
+// what the settings file turns on.
+fn cli_command() -> clap::Command {
+    let mut command = config::with_negations(Args::command());
+    command.build();
+    command
+}
+
+fn parse_args<I, T>(command: &clap::Command, argv: I) -> Result<Args, clap::Error>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let matches = command.clone().try_get_matches_from(argv)?;
+    Args::from_arg_matches(&matches)
+}
+
+// The clap message without its usage notes, on one line.
+fn first_line(error: &clap::Error) -> String {
+    let message = error.to_string();
+    let message = message.split("\n\n").next().unwrap_or_default();
+    let message = message.strip_prefix("error: ").unwrap_or(message);
+    message.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn check_config(args: &Args) -> Result<()> {
+    let config = load_config(args)?.ok_or_else(|| {
+        anyhow!(
+            "No settings file at {}",
+            config::default_path().map_or_else(|| "$XDG_CONFIG_HOME".to_string(), |p| p.display().to_string())
+        )
+    })?;
+    let command = cli_command();
+    let nothing_given = command.clone().get_matches_from(["fw16-pong-wars"]);
+
+    let profiles = std::iter::once(None).chain(config.profile_names().map(Some));
+    for profile in profiles {
+        let name = profile.map_or_else(|| "Main settings".to_string(), |p| format!("Profile {}", p));
+        let settings = config
+            .to_args(profile, &command, &nothing_given)
+            .map_err(|e| anyhow!("{}: {:#}", name, e))?;
+        let argv = std::iter::once("fw16-pong-wars".to_string()).chain(settings.iter().cloned());
+        parse_args(&command, argv).map_err(|e| anyhow!("{}: {}", name, first_line(&e)))?;
+        println!("{}: {}", name, if settings.is_empty() { "(none)".to_string() } else { settings.join(" ") });
+    }
+    println!("{} is valid", config.path().display());
+    Ok(())
 }
 
 fn main() -> Result<()> {
-    let args = Args::parse();
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    let (args, settings_file) = load_args(&argv)?;
+    logging::init(args.daemon, args.debug);
+    match &args.command {
+        Some(CliCommand::Ctl { action }) => return run_ctl(&args, action),
+        Some(CliCommand::Clear { action }) => return led_matrix::clear_modules(*action),
+        Some(CliCommand::Config { action: ConfigAction::Check }) => return check_config(&args),
+        None => {}
+    }
+    if let Some(path) = &settings_file {
+        match &args.profile {
+            Some(profile) => info!("Using settings from {} (profile {})", path.display(), profile),
+            None => info!("Using settings from {}", path.display()),
+        }
+    }
 
     let brightness_value = percent_to_led_value(args.brightness);
     let brightness_atomic = Arc::new(AtomicU8::new(brightness_value));
+    if args.auto_brightness {
+        let sensor = args
+            .als_path
+            .clone()
+            .or_else(ambient::find_sensor)
+            .ok_or_else(|| anyhow!("No ambient light sensor found; pass --als-path"))?;
+        info!("Auto brightness from {}", sensor.display());
+        ambient::spawn(
+            sensor,
+            args.brightness_curve.clone(),
+            args.brightness_hysteresis as f64,
+            brightness_atomic.clone(),
+        )?;
+    }
 
+    let mode = if let Some(layout) = args.layout.clone() {
+        OutputMode::Custom(layout)
+    } else if args.mirror {
+        OutputMode::Mirror {
+            flip_second: args.mirror_flip,
+        }
+    } else if args.dual_mode {
+        OutputMode::Dual {
+            gap_columns: args.gap_columns,
+        }
+    } else {
+        OutputMode::Single
+    };
+
+    // The matrix tracks what it last sent, which may be capped below the requested brightness.
     let mut matrix = LedMatrix::new_with_brightness(
-        brightness_atomic.clone(),
-        args.dual_mode,
-        DEFAULT_GRID_HEIGHT,
+        Arc::new(AtomicU8::new(brightness_atomic.load(Ordering::SeqCst))),
+        mode,
+        args.orientations.iter().cloned().collect(),
+        Duration::from_secs_f64(args.reconnect_max_wait),
+        args.daemon,
     )?;
-    matrix.set_brightness(brightness_value)?;
+    matrix.set_exit_action(args.on_exit);
 
     let width = matrix.width();
-    let max_fps = matrix.estimated_max_fps() as u8;
-    let effective_fps = args.speed.min(max_fps).max(1);
-    println!(
-        "Starting Pong Wars (width={} height={} speed={}fps brightness={}%)",
-        width, DEFAULT_GRID_HEIGHT, effective_fps, args.brightness
-    );
+    let height = matrix.height();
+    let effective_fps = args.speed.max(1);
 
     ctrlc::set_handler(|| {
-        println!("Received interrupt, shutting down...");
+        info!("Received interrupt, shutting down...");
         SHUTDOWN.store(true, Ordering::SeqCst);
     })?;
 
-    run_game_loop(&mut matrix, effective_fps, brightness_atomic, args.debug)?;
+    let initial_scene = if args.split { "split" } else { "pong-wars" };
+    let scene = build_scene(&args, initial_scene, width, height, matrix.gap_columns())?;
+    info!(
+        "Starting {} (width={} height={} speed={}fps brightness={}%)",
+        scene.name(),
+        width,
+        height,
+        effective_fps,
+        args.brightness
+    );
+
+    let (controls, requests) = Controls::new();
+    #[cfg(unix)]
+    let _control_server = if args.no_control_socket {
+        None
+    } else {
+        let path = args
+            .control_socket
+            .clone()
+            .unwrap_or_else(control::default_socket_path);
+        match control::ControlServer::spawn(path.clone(), controls.clone()) {
+            Ok(server) => {
+                info!("Listening for commands on {}", path.display());
+                Some(server)
+            }
+            Err(e) => {
+                warn!("Control socket disabled: {:#}", e);
+                None
+            }
+        }
+    };
+
+    #[cfg(unix)]
+    {
+        // Re-reads the settings file; only the speed and brightness change while running.
+        let auto_brightness = args.auto_brightness;
+        signals::spawn(&SHUTDOWN, controls.clone(), move || {
+            let (args, _) = load_args(&argv)?;
+            let mut commands = vec![Command::Speed { fps: args.speed }];
+            if !auto_brightness {
+                commands.push(Command::Brightness { percent: args.brightness });
+            }
+            Ok(commands)
+        })?;
+    }
+
+    let metrics = match args.metrics_listen {
+        Some(address) => {
+            let shared = SharedMetrics::default();
+            metrics::spawn(address, shared.clone())?;
+            info!("Serving metrics on http://{}/metrics", address);
+            Some(shared)
+        }
+        None => None,
+    };
+
+    if let Some(address) = args.web_listen {
+        let mirror = Arc::new(FrameMirror::default());
+        matrix.set_mirror(mirror.clone());
+        web::spawn(address, mirror, controls.clone())?;
+        info!("Serving the dashboard on http://{}/", address);
+    }
+
+    let keyboard = (!args.daemon)
+        .then(|| keyboard::spawn(controls))
+        .flatten();
+    if keyboard.is_some() {
+        println!("{}", keyboard::HELP);
+    }
+
+    let watcher = DeviceWatcher::spawn(matrix.detected_ports());
+    let power = args.battery_saver.then(|| {
+        (
+            PowerMonitor::spawn(args.power_supply_root.clone()),
+            PowerProfile {
+                fps: args.battery_fps,
+                brightness: percent_to_led_value(args.battery_brightness),
+                pause_below: args.battery_pause_below,
+            },
+        )
+    });
+    run_game_loop(
+        &mut matrix,
+        scene,
+        &|name, width, height, gap_columns| build_scene(&args, name, width, height, gap_columns),
+        &watcher,
+        brightness_atomic,
+        requests,
+        LoopOptions {
+            target_fps: effective_fps,
+            adaptive_fps: !args.fixed_fps,
+            pacing: if args.precise_timing {
+                PacingMode::Spin
+            } else {
+                PacingMode::Sleep
+            },
+            power,
+            notifier: Notifier::from_env(),
+            stats_interval: (args.stats_interval > 0).then(|| Duration::from_secs(args.stats_interval)),
+            stats_file: args.stats_file.clone(),
+            metrics,
+            auto_brightness: args.auto_brightness,
+        },
+    )?;
 
-    println!("Exited cleanly.");
+    // Runs the --on-exit action; on errors this happens when `matrix` goes out of scope.
+    drop(matrix);
+    info!("Exited cleanly.");
     Ok(())
 }
 
+fn run_ctl(args: &Args, action: &CtlAction) -> Result<()> {
+    let configured = match load_config(args)? {
+        Some(config) => config
+            .get(args.profile.as_deref(), &cli_command(), "control-socket")?
+            .map(PathBuf::from),
+        None => None,
+    };
+    let path = args
+        .control_socket
+        .clone()
+        .or(configured)
+        .unwrap_or_else(control::default_socket_path);
+    let reply = control::send(&path, &Command::from(action))?;
+    if !reply.ok {
+        return Err(anyhow!(reply.error.unwrap_or_else(|| "Command failed".to_string())));
+    }
+    if let Some(status) = reply.status {
+        println!("{}", status);
+    }
+    Ok(())
+}
+
+fn health_line(health: &[PortHealth]) -> String {
+    if health.is_empty() {
+        return "no modules connected".to_string();
+    }
+    health
+        .iter()
+        .map(|port| {
+            let state = match port.state {
+                PortState::Healthy => "healthy".to_string(),
+                PortState::Degraded => format!("degraded, {} failed writes", port.consecutive_errors),
+                PortState::Recovering => "recovering".to_string(),
+            };
+            match &port.last_error {
+                Some(error) if port.state != PortState::Healthy => {
+                    format!("{} {} ({})", port.port_name, state, error)
+                }
+                _ => format!("{} {}", port.port_name, state),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+const SCENE_NAMES: &str = "pong-wars or split";
+
+/// Inverts the bottom row up to the current frame rate (full width at 64 fps) and a pixel
+/// in the row above that moves one column per frame, so stutter is visible on the matrix.
+fn draw_overlay(frame: &mut Frame, fps: u8, frame_index: u64) {
+    let (width, height) = (frame.width(), frame.height());
+    if width == 0 || height < 2 {
+        return;
+    }
+    for x in 0..(fps as usize * width).div_ceil(64).min(width) {
+        let on = frame.get(x, height - 1);
+        frame.set(x, height - 1, !on);
+    }
+    let x = (frame_index % width as u64) as usize;
+    let on = frame.get(x, height - 2);
+    frame.set(x, height - 2, !on);
+}
+
+fn build_scene(
+    args: &Args,
+    name: &str,
+    width: usize,
+    height: usize,
+    gap_columns: usize,
+) -> Result<Box<dyn Scene>> {
+    let physics = args.physics.unwrap_or_default();
+    match name {
+        "pong-wars" => Ok(Box::new(GameState::with_options(
+            width,
+            height,
+            physics,
+            args.seed,
+        ))),
+        "split" => {
+            let (left_width, right_width, gap) = split_widths(width, gap_columns);
+            let right_seed = args
+                .right_seed
+                .or_else(|| args.seed.map(|seed| seed.wrapping_add(1)));
+            let left = GameState::with_options(left_width, height, physics, args.seed);
+            let right = GameState::with_options(
+                right_width,
+                height,
+                args.right_physics.unwrap_or(physics),
+                right_seed,
+            );
+            Ok(Box::new(Split::new(
+                Box::new(left),
+                Box::new(right),
+                left_width,
+                right_width,
+                gap,
+                height,
+            )))
+        }
+        other => Err(anyhow!(
+            "Unknown scene '{}' (expected {})",
+            other,
+            SCENE_NAMES
+        )),
+    }
+}
+
 static SHUTDOWN: AtomicBool = AtomicBool::new(false);
 
+const SCORE_REPORT_INTERVAL: Duration = Duration::from_secs(2);
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(250);
+const METRICS_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
+
+struct LoopOptions {
+    target_fps: u8,
+    adaptive_fps: bool,
+    pacing: PacingMode,
+    power: Option<(PowerMonitor, PowerProfile)>,
+    notifier: Notifier,
+    stats_interval: Option<Duration>,
+    stats_file: Option<PathBuf>,
+    metrics: Option<SharedMetrics>,
+    // Brightness is driven by the light sensor, so manual changes are refused.
+    auto_brightness: bool,
+}
+
+fn power_line(source: PowerSource, limits: PowerLimits) -> String {
+    let PowerSource::Battery { capacity } = source else {
+        return "on AC power; running at full rate and brightness".to_string();
+    };
+    let charge = capacity.map_or_else(String::new, |c| format!(" ({}%)", c));
+    if limits.paused {
+        return format!("on battery{}; battery low, pausing", charge);
+    }
+    format!(
+        "on battery{}; limiting to {} fps and {}% brightness",
+        charge,
+        limits.max_fps.unwrap_or_default(),
+        led_value_to_percent(limits.max_brightness.unwrap_or_default())
+    )
+}
+
+fn apply_fps_cap(frame_rate: &mut Option<FrameRateController>, cap: u8) -> Duration {
+    let fps = match frame_rate {
+        Some(rate) => {
+            rate.set_cap(cap);
+            rate.fps()
+        }
+        None => cap,
+    };
+    Duration::from_secs_f64(1.0 / fps as f64)
+}
+
+type SceneFactory<'a> = dyn Fn(&str, usize, usize, usize) -> Result<Box<dyn Scene>> + 'a;
+
 fn run_game_loop(
     matrix: &mut LedMatrix,
-    target_fps: u8,
+    mut scene: Box<dyn Scene>,
+    make_scene: &SceneFactory,
+    watcher: &DeviceWatcher,
     brightness: Arc<AtomicU8>,
-    debug: bool,
+    requests: Receiver<Request>,
+    options: LoopOptions,
 ) -> Result<()> {
-    let width = matrix.width();
-    let mut game_state = GameState::new(width, DEFAULT_GRID_HEIGHT);
+    let LoopOptions {
+        target_fps,
+        adaptive_fps,
+        pacing,
+        power,
+        mut notifier,
+        stats_interval,
+        stats_file,
+        metrics,
+        auto_brightness,
+    } = options;
+    let mut pacer = Pacer::new(pacing);
+    let mut frame = Frame::new(matrix.width(), matrix.height());
 
-    let frame_duration = Duration::from_secs_f64(1.0 / target_fps as f64);
+    let mut frame_duration = Duration::from_secs_f64(1.0 / target_fps as f64);
     let mut next_frame_time = Instant::now();
+    let mut frame_rate = adaptive_fps.then(|| FrameRateController::new(target_fps, next_frame_time));
     let mut last_frame_start = next_frame_time;
     let mut frame_index: u64 = 0;
+    let mut last_score_report = next_frame_time;
+    let mut stats = FrameStats::new(next_frame_time);
+    let mut last_stats_report = next_frame_time;
+    let mut last_metrics_update = (next_frame_time, 0);
+
+    let mut power_limits = PowerLimits::default();
+    let mut speed = target_fps;
+    let mut fps_cap = target_fps;
+    let mut paused = false;
+    let mut step = false;
+    let mut overlay = false;
 
     let mut last_sent_brightness = brightness.load(Ordering::SeqCst);
+    notifier.status(&health_line(&matrix.port_health()));
+    notifier.ready();
     while !SHUTDOWN.load(Ordering::SeqCst) {
         let now = Instant::now();
+        notifier.watchdog(now);
 
-        if now >= next_frame_time {
-            if debug {
-                let actual_dt = now.saturating_duration_since(last_frame_start);
-                let scheduled_next = next_frame_time + frame_duration;
-                println!(
-                    "[debug] frame {} start={:?} deadline={:?} actual_dt={:?} next_deadline={:?}",
-                    frame_index, now, next_frame_time, actual_dt, scheduled_next
-                );
-            }
+        if (paused || power_limits.paused) && !step {
+            // Keep the last frame on the modules and only wake up for power and hotplug changes.
+            pacer.wait_until(now + PAUSED_POLL_INTERVAL);
+            next_frame_time = Instant::now();
+            last_frame_start = next_frame_time;
+        } else if step || now >= next_frame_time {
+            step = false;
+            let actual_dt = now.saturating_duration_since(last_frame_start);
+            stats.record_frame(actual_dt);
 
-            game_state.update();
+            scene.tick(actual_dt);
+            frame.clear();
+            scene.draw(&mut frame);
+            if overlay {
+                let fps = frame_rate.as_ref().map_or(fps_cap, |rate| rate.fps());
+                draw_overlay(&mut frame, fps, frame_index);
+            }
 
-            if let Err(e) = matrix.render(&game_state) {
-                eprintln!("Render error: {}", e);
+            if let Err(e) = matrix.render(&frame) {
+                stats.record_render_error();
+                rate_limited!(Level::Error, "Render error: {}", e);
                 std::thread::sleep(Duration::from_millis(10));
             }
+            if let Some(health) = matrix.take_health_change() {
+                let line = health_line(&health);
+                info!("Module health: {}", line);
+                notifier.status(&line);
+            }
+            if let Some(rate) = frame_rate.as_mut() {
+                if let Some(change) = rate.update(now, matrix.throughput()) {
+                    frame_duration = Duration::from_secs_f64(1.0 / rate.fps() as f64);
+                    if change == RateChange::Errors {
+                        warn!("Write errors detected; lowering frame rate to {} fps", rate.fps());
+                    } else {
+                        debug!("Frame rate now {} fps ({:?})", rate.fps(), change);
+                    }
+                }
+            }
 
             let scheduled_next = next_frame_time + frame_duration;
             if now.saturating_duration_since(next_frame_time) > frame_duration {
+                stats.record_deadline_miss();
                 next_frame_time = now + frame_duration;
             } else {
                 next_frame_time = scheduled_next;
             }
             last_frame_start = now;
             frame_index = frame_index.wrapping_add(1);
-        } else {
-            let sleep_duration = next_frame_time.saturating_duration_since(now);
 
-            if let Some(coarse_sleep) = sleep_duration.checked_sub(Duration::from_millis(1)) {
-                if debug {
-                    println!(
-                        "[debug] sleeping {:?} before spin (coarse={:?})",
-                        sleep_duration, coarse_sleep
-                    );
+            if now.duration_since(last_score_report) >= SCORE_REPORT_INTERVAL {
+                let scores = scene.scores();
+                if scores.len() > 1 {
+                    info!("Scores: {}", score_line(&scores));
                 }
-                std::thread::sleep(coarse_sleep);
+                last_score_report = now;
             }
+        } else {
+            pacer.wait_until(next_frame_time);
+        }
 
-            while Instant::now() < next_frame_time {
-                std::hint::spin_loop();
+        if stats_interval.is_some_and(|interval| now.duration_since(last_stats_report) >= interval) {
+            info!(target: "stats", "Stats: {}", stats.report(now, pacer.jitter(), matrix.port_stats()));
+            last_stats_report = now;
+        }
+
+        if let Some(metrics) = metrics.as_ref() {
+            let (last_update, last_frames) = last_metrics_update;
+            let elapsed = now.duration_since(last_update);
+            if elapsed >= METRICS_UPDATE_INTERVAL {
+                let update = Metrics {
+                    scores: scene.scores(),
+                    fps: frame_index.wrapping_sub(last_frames) as f64 / elapsed.as_secs_f64(),
+                    stats: Some(stats.report(now, pacer.jitter(), matrix.port_stats())),
+                };
+                if let Ok(mut shared) = metrics.lock() {
+                    *shared = update;
+                }
+                last_metrics_update = (now, frame_index);
             }
-            if debug {
-                println!(
-                    "[debug] spin-wait completed; woke at {:?} for deadline {:?}",
-                    Instant::now(),
-                    next_frame_time
-                );
+        }
+
+        if let Some((monitor, profile)) = power.as_ref() {
+            if let Some(source) = monitor.take_change() {
+                let limits = profile.limits(source);
+                if limits != power_limits {
+                    info!("Power: {}", power_line(source, limits));
+                    power_limits = limits;
+                }
             }
         }
 
-        let desired_brightness = brightness.load(Ordering::SeqCst);
+        while let Ok(request) = requests.try_recv() {
+            let reply = match &request.command {
+                Command::Status => {
+                    let throughput = matrix.throughput();
+                    let jitter = pacer.jitter();
+                    Reply::status(Status {
+                        scene: scene.name().to_string(),
+                        width: frame.width(),
+                        height: frame.height(),
+                        fps: frame_rate.as_ref().map_or(fps_cap, |rate| rate.fps()),
+                        speed,
+                        brightness: led_value_to_percent(brightness.load(Ordering::SeqCst)),
+                        applied_brightness: led_value_to_percent(last_sent_brightness),
+                        paused: paused || power_limits.paused,
+                        modules: health_line(&matrix.port_health()),
+                        scores: scene.scores(),
+                        frames: frame_index,
+                        write_latency_ms: throughput
+                            .write_latency
+                            .map(|l| l.as_secs_f64() * 1000.0),
+                        write_errors: throughput.write_errors,
+                        dropped_frames: throughput.dropped_frames,
+                        mean_jitter_ms: jitter.mean().as_secs_f64() * 1000.0,
+                        max_jitter_ms: jitter.max.as_secs_f64() * 1000.0,
+                    })
+                }
+                Command::Brightness { .. } if auto_brightness => {
+                    Reply::error("brightness follows the ambient light sensor (--auto-brightness)")
+                }
+                Command::Brightness { percent } if *percent <= 100 => {
+                    brightness.store(percent_to_led_value(*percent), Ordering::SeqCst);
+                    Reply::ok()
+                }
+                Command::Brightness { .. } => Reply::error("brightness must be 0-100"),
+                Command::Speed { fps } if (1..=64).contains(fps) => {
+                    speed = *fps;
+                    Reply::ok()
+                }
+                Command::Speed { .. } => Reply::error("speed must be 1-64 fps"),
+                Command::Pause => {
+                    paused = true;
+                    Reply::ok()
+                }
+                Command::Resume => {
+                    paused = false;
+                    Reply::ok()
+                }
+                Command::TogglePause => {
+                    paused = !paused;
+                    Reply::ok()
+                }
+                Command::Step => {
+                    paused = true;
+                    step = true;
+                    Reply::ok()
+                }
+                Command::ToggleOverlay => {
+                    overlay = !overlay;
+                    Reply::ok()
+                }
+                Command::Reset => {
+                    scene.handle_command(&SceneCommand::Reset);
+                    Reply::ok()
+                }
+                Command::Scene { name } => {
+                    match make_scene(name, frame.width(), frame.height(), matrix.gap_columns()) {
+                        Ok(new_scene) => {
+                            scene = new_scene;
+                            info!("Switched to {}", scene.name());
+                            Reply::ok()
+                        }
+                        Err(e) => Reply::error(e.to_string()),
+                    }
+                }
+                Command::Quit => {
+                    info!("Quit requested, shutting down...");
+                    SHUTDOWN.store(true, Ordering::SeqCst);
+                    Reply::ok()
+                }
+            };
+            request.respond(reply);
+        }
+
+        let cap = power_limits.max_fps.map_or(speed, |fps| fps.min(speed));
+        if cap != fps_cap {
+            fps_cap = cap;
+            frame_duration = apply_fps_cap(&mut frame_rate, cap);
+        }
+
+        let mut desired_brightness = brightness.load(Ordering::SeqCst);
+        if let Some(max) = power_limits.max_brightness {
+            desired_brightness = desired_brightness.min(max);
+        }
         if desired_brightness != last_sent_brightness {
-            matrix.set_brightness(desired_brightness)?;
+            matrix.set_brightness(desired_brightness);
             last_sent_brightness = desired_brightness;
         }
+
+        if let Some(modules) = watcher.take_change() {
+            matrix.apply_modules(modules);
+        }
+        // Hotplug may have reshaped the output; keep the game running at the new size.
+        if frame.width() != matrix.width() || frame.height() != matrix.height() {
+            scene.resize(matrix.width(), matrix.height(), matrix.gap_columns());
+            frame = Frame::new(matrix.width(), matrix.height());
+        }
+    }
+
+    notifier.stopping();
+    let report = stats.report(Instant::now(), pacer.jitter(), matrix.port_stats());
+    info!(target: "stats", "Stats ({:?} pacing): {}", pacer.mode(), report);
+    if let Some(path) = stats_file {
+        let json = serde_json::to_string_pretty(&report)?;
+        std::fs::write(&path, json + "\n")
+            .map_err(|e| anyhow!("Failed writing {}: {}", path.display(), e))?;
     }
 
     Ok(())
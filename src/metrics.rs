@@ -0,0 +1,265 @@
+use anyhow::{Context, Result};
+use log::debug;
+use std::fmt::Write as _;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::http;
+use crate::scene::Score;
+use crate::stats::{PortReport, StatsReport};
+
+const PREFIX: &str = "fw16_pong_wars";
+
+/// Latest values published by the frame loop for the metrics endpoint.
+#[derive(Clone, Debug, Default)]
+pub struct Metrics {
+    pub scores: Vec<Score>,
+    /// Frames per second actually drawn since the previous update.
+    pub fps: f64,
+    pub stats: Option<StatsReport>,
+}
+
+pub type SharedMetrics = Arc<Mutex<Metrics>>;
+
+/// Serves `metrics` in the Prometheus text format on `GET /metrics` from a background thread.
+pub fn spawn(address: SocketAddr, metrics: SharedMetrics) -> Result<()> {
+    let listener =
+        TcpListener::bind(address).with_context(|| format!("Failed binding {}", address))?;
+    serve_on(listener, metrics);
+    Ok(())
+}
+
+fn serve_on(listener: TcpListener, metrics: SharedMetrics) {
+    let started = Instant::now();
+
+    thread::spawn(move || {
+        // Scrapes are rare and cheap, so connections are handled one at a time.
+        for stream in listener.incoming().flatten() {
+            if let Err(e) = serve(stream, &metrics, started) {
+                debug!("Metrics request failed: {}", e);
+            }
+        }
+    });
+}
+
+fn serve(stream: TcpStream, metrics: &SharedMetrics, started: Instant) -> Result<()> {
+    let request = http::read_request(&stream)?;
+    if request.method != "GET" || request.path != "/metrics" {
+        return http::not_found(&stream);
+    }
+    let snapshot = metrics.lock().map(|m| m.clone()).unwrap_or_default();
+    let body = render(&snapshot, started.elapsed());
+    http::respond(&stream, "200 OK", "text/plain; version=0.0.4", &body)
+}
+
+fn render(metrics: &Metrics, uptime: Duration) -> String {
+    let mut out = String::new();
+    sample_family(
+        &mut out,
+        "uptime_seconds",
+        "gauge",
+        "Seconds since the metrics endpoint started.",
+        [(String::new(), format!("{:.3}", uptime.as_secs_f64()))],
+    );
+    sample_family(
+        &mut out,
+        "fps",
+        "gauge",
+        "Frames drawn per second over the last second.",
+        [(String::new(), format!("{:.2}", metrics.fps))],
+    );
+    let scores = metrics.scores.iter().flat_map(|score| {
+        [("day", score.day), ("night", score.night)].map(|(side, tiles)| {
+            (
+                format!("{{game=\"{}\",side=\"{}\"}}", escape(&score.label), side),
+                tiles.to_string(),
+            )
+        })
+    });
+    sample_family(
+        &mut out,
+        "score",
+        "gauge",
+        "Tiles owned by each side of each game.",
+        scores,
+    );
+
+    let Some(stats) = &metrics.stats else {
+        return out;
+    };
+
+    sample_family(
+        &mut out,
+        "frames_total",
+        "counter",
+        "Frames drawn since startup.",
+        [(String::new(), stats.frames.to_string())],
+    );
+    sample_family(
+        &mut out,
+        "deadline_misses_total",
+        "counter",
+        "Frames started more than a frame interval late.",
+        [(String::new(), stats.deadline_misses.to_string())],
+    );
+    sample_family(
+        &mut out,
+        "render_errors_total",
+        "counter",
+        "Frames that could not be sent because no module was responding.",
+        [(String::new(), stats.render_errors.to_string())],
+    );
+
+    let mut cumulative = 0;
+    let buckets = stats.frame_time_histogram.iter().map(|bucket| {
+        cumulative += bucket.count;
+        let le = bucket
+            .le_ms
+            .map_or_else(|| "+Inf".to_string(), |ms| format!("{}", ms as f64 / 1000.0));
+        (format!("_bucket{{le=\"{}\"}}", le), cumulative.to_string())
+    });
+    let sum = stats.mean_frame_time_ms * stats.frames as f64 / 1000.0;
+    sample_family(
+        &mut out,
+        "frame_time_seconds",
+        "histogram",
+        "Time between the starts of consecutive frames.",
+        buckets.chain([
+            ("_sum".to_string(), format!("{:.6}", sum)),
+            ("_count".to_string(), stats.frames.to_string()),
+        ]),
+    );
+
+    let per_port = |value: fn(&PortReport) -> u64| {
+        stats
+            .ports
+            .iter()
+            .map(move |port| (format!("{{port=\"{}\"}}", escape(&port.port)), value(port).to_string()))
+    };
+    sample_family(
+        &mut out,
+        "serial_bytes_written_total",
+        "counter",
+        "Bytes written to each module.",
+        per_port(|p| p.bytes_written),
+    );
+    sample_family(
+        &mut out,
+        "write_errors_total",
+        "counter",
+        "Failed writes to each module.",
+        per_port(|p| p.write_errors),
+    );
+    sample_family(
+        &mut out,
+        "dropped_frames_total",
+        "counter",
+        "Frames skipped because a module had not finished the previous one.",
+        per_port(|p| p.dropped_frames),
+    );
+    sample_family(
+        &mut out,
+        "reconnects_total",
+        "counter",
+        "Times each module was reopened after failing.",
+        per_port(|p| p.reconnects),
+    );
+    out
+}
+
+// Writes one metric family; each sample is a suffix after the family name (labels, or
+// `_bucket{...}` for histograms) and its value.
+fn sample_family(
+    out: &mut String,
+    name: &str,
+    kind: &str,
+    help: &str,
+    samples: impl IntoIterator<Item = (String, String)>,
+) {
+    let _ = writeln!(out, "# HELP {}_{} {}", PREFIX, name, help);
+    let _ = writeln!(out, "# TYPE {}_{} {}", PREFIX, name, kind);
+    for (suffix, value) in samples {
+        let _ = writeln!(out, "{}_{}{} {}", PREFIX, name, suffix, value);
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::led_matrix::PortStats;
+    use crate::pacing::Jitter;
+    use crate::stats::FrameStats;
+    use std::io::{Read, Write};
+
+    fn scrape(address: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_published_metrics() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let shared = SharedMetrics::default();
+        serve_on(listener, shared.clone());
+
+        let started = Instant::now();
+        let mut stats = FrameStats::new(started);
+        for ms in [4, 16, 16, 300] {
+            stats.record_frame(Duration::from_millis(ms));
+        }
+        let port = PortStats {
+            port_name: "/dev/tty\"ACM0\"".to_string(),
+            bytes_written: 1234,
+            write_errors: 2,
+            ..PortStats::default()
+        };
+        *shared.lock().unwrap() = Metrics {
+            scores: vec![Score {
+                label: "left\\game\n".to_string(),
+                day: 200,
+                night: 106,
+            }],
+            fps: 59.5,
+            stats: Some(stats.report(started, Jitter::default(), vec![port])),
+        };
+
+        let response = scrape(address, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let lines: Vec<&str> = body.lines().collect();
+        for expected in [
+            "# TYPE fw16_pong_wars_fps gauge",
+            "fw16_pong_wars_fps 59.50",
+            r#"fw16_pong_wars_score{game="left\\game\n",side="day"} 200"#,
+            r#"fw16_pong_wars_score{game="left\\game\n",side="night"} 106"#,
+            "fw16_pong_wars_frames_total 4",
+            "# TYPE fw16_pong_wars_frame_time_seconds histogram",
+            r#"fw16_pong_wars_frame_time_seconds_bucket{le="0.005"} 1"#,
+            r#"fw16_pong_wars_frame_time_seconds_bucket{le="0.017"} 3"#,
+            r#"fw16_pong_wars_frame_time_seconds_bucket{le="0.25"} 3"#,
+            r#"fw16_pong_wars_frame_time_seconds_bucket{le="+Inf"} 4"#,
+            "fw16_pong_wars_frame_time_seconds_sum 0.336000",
+            "fw16_pong_wars_frame_time_seconds_count 4",
+            r#"fw16_pong_wars_serial_bytes_written_total{port="/dev/tty\"ACM0\""} 1234"#,
+            r#"fw16_pong_wars_write_errors_total{port="/dev/tty\"ACM0\""} 2"#,
+        ] {
+            assert!(lines.contains(&expected), "missing '{}' in:\n{}", expected, body);
+        }
+
+        assert!(scrape(address, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -0,0 +1,294 @@
+use std::time::{Duration, Instant};
+
+use crate::led_matrix::Throughput;
+
+const ADAPT_INTERVAL: Duration = Duration::from_millis(500);
+const ERROR_HOLD: Duration = Duration::from_secs(5); // No speed-ups for this long after errors
+const MIN_FPS: f64 = 1.0;
+const RAISE_STEP_FPS: f64 = 2.0;
+const ERROR_BACKOFF: f64 = 0.5;
+const OVERLOAD_BACKOFF: f64 = 0.85;
+const OVERLOAD_LATENCY: f64 = 0.9; // Share of the frame budget a write may take before backing off
+const HEADROOM_LATENCY: f64 = 0.5; // Share of the frame budget below which the rate is raised
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateChange {
+    /// Writes failed; the rate was cut sharply.
+    Errors,
+    /// Writes took most of the frame budget or frames were skipped.
+    Overloaded,
+    /// Writes finish well within the frame budget.
+    Headroom,
+}
+
+/// Adjusts the frame rate to what the modules actually sustain, never above the user's cap:
+/// halves it when writes fail, trims it when writes are slow or frames get skipped, and
+/// creeps back up while there is headroom.
+pub struct FrameRateController {
+    cap: f64,
+    fps: f64,
+    window_start: Instant,
+    last: Option<Throughput>,
+    hold_until: Instant,
+}
+
+impl FrameRateController {
+    pub fn new(cap: u8, now: Instant) -> Self {
+        FrameRateController {
+            cap: cap.max(1) as f64,
+            fps: cap.max(1) as f64,
+            window_start: now,
+            last: None,
+            hold_until: now,
+        }
+    }
+
+    pub fn fps(&self) -> u8 {
+        self.fps.round() as u8
+    }
+
+    /// Changes the upper limit; a lower cap takes effect immediately, a higher one is
+    /// reached gradually while there is headroom.
+    pub fn set_cap(&mut self, cap: u8) {
+        self.cap = cap.max(1) as f64;
+        self.fps = self.fps.min(self.cap);
+    }
+
+    /// Feeds the latest measurements; returns the reason if the rounded rate changed.
+    pub fn update(&mut self, now: Instant, sample: Throughput) -> Option<RateChange> {
+        if now.duration_since(self.window_start) < ADAPT_INTERVAL {
+            return None;
+        }
+        let Some(last) = self.last.replace(sample) else {
+            self.window_start = now;
+            return None;
+        };
+        self.window_start = now;
+
+        let errors = sample.write_errors.saturating_sub(last.write_errors);
+        let dropped = sample.dropped_frames.saturating_sub(last.dropped_frames);
+        let budget = 1.0 / self.fps;
+        let latency = sample.write_latency.map(|l| l.as_secs_f64());
+        let before = self.fps();
+
+        let change = if errors > 0 {
+            self.fps = (self.fps * ERROR_BACKOFF).max(MIN_FPS);
+            self.hold_until = now + ERROR_HOLD;
+            RateChange::Errors
+        } else if dropped > 0 || latency.is_some_and(|l| l > budget * OVERLOAD_LATENCY) {
+            self.fps = (self.fps * OVERLOAD_BACKOFF).max(MIN_FPS);
+            RateChange::Overloaded
+        } else if now >= self.hold_until && latency.is_none_or(|l| l < budget * HEADROOM_LATENCY) {
+            self.fps = (self.fps + RAISE_STEP_FPS).min(self.cap);
+            RateChange::Headroom
+        } else {
+            return None;
+        };
+
+        (self.fps() != before).then_some(change)
+    }
+}
+
+const SPIN_MARGIN: Duration = Duration::from_millis(1); // Spin mode sleeps until this close
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PacingMode {
+    /// Sleep until the frame deadline. Uses absolute-deadline `clock_nanosleep` on Linux.
+    Sleep,
+    /// Sleep until shortly before the deadline, then busy-wait. Most precise, costs battery.
+    Spin,
+}
+
+/// How late the loop woke up relative to its frame deadlines.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Jitter {
+    pub samples: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl Jitter {
+    pub fn mean(&self) -> Duration {
+        if self.samples == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(self.total.as_secs_f64() / self.samples as f64)
+        }
+    }
+}
+
+/// Waits for frame deadlines and measures how precisely they are hit.
+pub struct Pacer {
+    mode: PacingMode,
+    jitter: Jitter,
+}
+
+impl Pacer {
+    pub fn new(mode: PacingMode) -> Self {
+        Pacer {
+            mode,
+            jitter: Jitter::default(),
+        }
+    }
+
+    pub fn mode(&self) -> PacingMode {
+        self.mode
+    }
+
+    pub fn jitter(&self) -> Jitter {
+        self.jitter
+    }
+
+    /// Blocks until `deadline` and returns how late the wake-up was.
+    pub fn wait_until(&mut self, deadline: Instant) -> Duration {
+        match self.mode {
+            PacingMode::Sleep => sleep_until(deadline),
+            PacingMode::Spin => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if let Some(coarse_sleep) = remaining.checked_sub(SPIN_MARGIN) {
+                    std::thread::sleep(coarse_sleep);
+                }
+                while Instant::now() < deadline {
+                    std::hint::spin_loop();
+                }
+            }
+        }
+
+        let late = Instant::now().saturating_duration_since(deadline);
+        self.jitter.samples += 1;
+        self.jitter.total = self.jitter.total.saturating_add(late);
+        self.jitter.max = self.jitter.max.max(late);
+        late
+    }
+}
+
+// `Instant` is CLOCK_MONOTONIC on Linux, so the deadline can be handed to the kernel as an
+// absolute time; unlike a relative sleep this does not drift when the thread is preempted.
+#[cfg(target_os = "linux")]
+fn sleep_until(deadline: Instant) {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // SAFETY: `now` is a valid timespec for clock_gettime to fill in.
+    if unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) } != 0 {
+        std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        return;
+    }
+
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let nanos = now.tv_nsec as u64 + remaining.subsec_nanos() as u64;
+    let target = libc::timespec {
+        tv_sec: now.tv_sec
+            + remaining.as_secs() as libc::time_t
+            + (nanos / 1_000_000_000) as libc::time_t,
+        tv_nsec: (nanos % 1_000_000_000) as _,
+    };
+    loop {
+        // SAFETY: `target` is a valid timespec; no remaining-time output is requested.
+        let result = unsafe {
+            libc::clock_nanosleep(
+                libc::CLOCK_MONOTONIC,
+                libc::TIMER_ABSTIME,
+                &target,
+                std::ptr::null_mut(),
+            )
+        };
+        if result != libc::EINTR {
+            break;
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sleep_until(deadline: Instant) {
+    std::thread::sleep(deadline.saturating_duration_since(Instant::now()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(write_errors: u64, dropped_frames: u64, latency_ms: Option<u64>) -> Throughput {
+        Throughput {
+            write_latency: latency_ms.map(Duration::from_millis),
+            write_errors,
+            dropped_frames,
+        }
+    }
+
+    /// A controller that has seen its first window, so the next update adapts.
+    fn primed(cap: u8, start: Instant) -> FrameRateController {
+        let mut controller = FrameRateController::new(cap, start);
+        assert_eq!(controller.update(start + ADAPT_INTERVAL, sample(0, 0, None)), None);
+        controller
+    }
+
+    fn window(start: Instant, n: u32) -> Instant {
+        start + ADAPT_INTERVAL * (n + 1)
+    }
+
+    #[test]
+    fn errors_halve_the_rate_and_hold_off_raising_it() {
+        let start = Instant::now();
+        let mut controller = primed(30, start);
+        assert_eq!(
+            controller.update(window(start, 1), sample(1, 0, Some(1))),
+            Some(RateChange::Errors)
+        );
+        assert_eq!(controller.fps(), 15);
+
+        let mut now = window(start, 2);
+        while now < window(start, 1) + ERROR_HOLD {
+            assert_eq!(controller.update(now, sample(1, 0, Some(1))), None);
+            assert_eq!(controller.fps(), 15);
+            now += ADAPT_INTERVAL;
+        }
+        assert_eq!(controller.update(now, sample(1, 0, Some(1))), Some(RateChange::Headroom));
+        assert_eq!(controller.fps(), 17);
+    }
+
+    #[test]
+    fn dropped_frames_and_slow_writes_trim_the_rate() {
+        let start = Instant::now();
+        let mut controller = primed(30, start);
+        assert_eq!(
+            controller.update(window(start, 1), sample(0, 2, Some(1))),
+            Some(RateChange::Overloaded)
+        );
+        assert_eq!(controller.fps(), 26); // 30 * 0.85
+
+        // 38ms is more than 90% of the 39ms budget at 25.5 fps.
+        assert_eq!(
+            controller.update(window(start, 2), sample(0, 2, Some(38))),
+            Some(RateChange::Overloaded)
+        );
+        assert_eq!(controller.fps(), 22); // 25.5 * 0.85
+    }
+
+    #[test]
+    fn headroom_raises_the_rate_in_steps_up_to_the_cap() {
+        let start = Instant::now();
+        let mut controller = primed(30, start);
+        controller.set_cap(10);
+        controller.set_cap(15);
+        assert_eq!(controller.fps(), 10);
+
+        let mut rates = Vec::new();
+        for n in 1..=4 {
+            controller.update(window(start, n), sample(0, 0, Some(1)));
+            rates.push(controller.fps());
+        }
+        assert_eq!(rates, [12, 14, 15, 15]);
+    }
+
+    #[test]
+    fn lowering_the_cap_takes_effect_immediately() {
+        let start = Instant::now();
+        let mut controller = FrameRateController::new(30, start);
+        controller.set_cap(12);
+        assert_eq!(controller.fps(), 12);
+        controller.set_cap(0);
+        assert_eq!(controller.fps(), 1);
+    }
+}
@@ -0,0 +1,219 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub const DEFAULT_POWER_SUPPLY_ROOT: &str = "/sys/class/power_supply";
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where the laptop is drawing power from, as reported under `/sys/class/power_supply`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PowerSource {
+    /// Plugged in, or no battery was found.
+    Ac,
+    /// Running on battery; `capacity` is the lowest charge percentage of all batteries.
+    Battery { capacity: Option<u8> },
+}
+
+/// What to do while running on battery.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PowerProfile {
+    /// Frame rate cap on battery.
+    pub fps: u8,
+    /// Brightness cap on battery, as an LED value (0-255).
+    pub brightness: u8,
+    /// Pause the game at or below this charge percentage; 0 never pauses.
+    pub pause_below: u8,
+}
+
+/// Limits the frame loop applies on top of the user's settings.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PowerLimits {
+    pub max_fps: Option<u8>,
+    pub max_brightness: Option<u8>,
+    pub paused: bool,
+}
+
+impl PowerProfile {
+    pub fn limits(&self, source: PowerSource) -> PowerLimits {
+        match source {
+            PowerSource::Ac => PowerLimits::default(),
+            PowerSource::Battery { capacity } => PowerLimits {
+                max_fps: Some(self.fps),
+                max_brightness: Some(self.brightness),
+                paused: self.pause_below > 0 && capacity.is_some_and(|c| c <= self.pause_below),
+            },
+        }
+    }
+}
+
+/// Polls the power supply class on a background thread and reports when the power source
+/// or battery charge changes.
+pub struct PowerMonitor {
+    pending: Arc<Mutex<Option<PowerSource>>>,
+}
+
+impl PowerMonitor {
+    /// The state at startup is reported by the first `take_change` call.
+    pub fn spawn(root: PathBuf) -> Self {
+        let mut known = read_power_source(&root);
+        let pending = Arc::new(Mutex::new(Some(known)));
+        let shared = pending.clone();
+
+        thread::spawn(move || loop {
+            thread::sleep(POLL_INTERVAL);
+            let source = read_power_source(&root);
+            if source != known {
+                known = source;
+                if let Ok(mut slot) = shared.lock() {
+                    *slot = Some(source);
+                }
+            }
+        });
+
+        PowerMonitor { pending }
+    }
+
+    /// Returns the new power source if it changed since the last call.
+    pub fn take_change(&self) -> Option<PowerSource> {
+        self.pending.lock().ok().and_then(|mut slot| slot.take())
+    }
+}
+
+/// Treats the laptop as on battery when no mains/USB supply is online and at least one
+/// battery reports that it is discharging.
+pub fn read_power_source(root: &Path) -> PowerSource {
+    let Ok(entries) = fs::read_dir(root) else {
+        return PowerSource::Ac;
+    };
+
+    let mut external_online = false;
+    let mut discharging = false;
+    let mut capacity: Option<u8> = None;
+    for entry in entries.flatten() {
+        let supply = entry.path();
+        match read_attribute(&supply, "type").as_deref() {
+            Some("Battery") => {
+                if read_attribute(&supply, "status").as_deref() == Some("Discharging") {
+                    discharging = true;
+                }
+                if let Some(percent) =
+                    read_attribute(&supply, "capacity").and_then(|c| c.parse::<u8>().ok())
+                {
+                    capacity = Some(capacity.map_or(percent, |c| c.min(percent)));
+                }
+            }
+            Some(_) if read_attribute(&supply, "online").as_deref() == Some("1") => {
+                external_online = true;
+            }
+            _ => {}
+        }
+    }
+
+    if discharging && !external_online {
+        PowerSource::Battery { capacity }
+    } else {
+        PowerSource::Ac
+    }
+}
+
+fn read_attribute(supply: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(supply.join(name))
+        .ok()
+        .map(|value| value.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn supply(root: &TempDir, name: &str, attributes: &[(&str, &str)]) {
+        let dir = root.path().join(name);
+        fs::create_dir(&dir).unwrap();
+        for (attribute, value) in attributes {
+            fs::write(dir.join(attribute), format!("{}\n", value)).unwrap();
+        }
+    }
+
+    fn battery(root: &TempDir, name: &str, status: &str, capacity: Option<&str>) {
+        let mut attributes = vec![("type", "Battery"), ("status", status)];
+        if let Some(capacity) = capacity {
+            attributes.push(("capacity", capacity));
+        }
+        supply(root, name, &attributes);
+    }
+
+    const PROFILE: PowerProfile = PowerProfile {
+        fps: 30,
+        brightness: 64,
+        pause_below: 10,
+    };
+
+    #[test]
+    fn ac_online_wins_over_discharging_battery() {
+        let root = TempDir::new().unwrap();
+        supply(&root, "ACAD", &[("type", "Mains"), ("online", "1")]);
+        battery(&root, "BAT1", "Discharging", Some("80"));
+        assert_eq!(read_power_source(root.path()), PowerSource::Ac);
+    }
+
+    #[test]
+    fn offline_ac_uses_lowest_battery_capacity() {
+        let root = TempDir::new().unwrap();
+        supply(&root, "ACAD", &[("type", "Mains"), ("online", "0")]);
+        battery(&root, "BAT0", "Discharging", Some("42"));
+        battery(&root, "BAT1", "Full", Some("17"));
+        assert_eq!(
+            read_power_source(root.path()),
+            PowerSource::Battery { capacity: Some(17) }
+        );
+    }
+
+    #[test]
+    fn missing_capacity_is_unknown() {
+        let root = TempDir::new().unwrap();
+        battery(&root, "BAT1", "Discharging", None);
+        battery(&root, "BAT2", "Discharging", Some("unknown"));
+        assert_eq!(
+            read_power_source(root.path()),
+            PowerSource::Battery { capacity: None }
+        );
+    }
+
+    #[test]
+    fn no_discharging_battery_or_no_tree_is_ac() {
+        let root = TempDir::new().unwrap();
+        battery(&root, "BAT1", "Charging", Some("50"));
+        assert_eq!(read_power_source(root.path()), PowerSource::Ac);
+        assert_eq!(read_power_source(&root.path().join("missing")), PowerSource::Ac);
+    }
+
+    #[test]
+    fn limits_apply_only_on_battery() {
+        assert_eq!(PROFILE.limits(PowerSource::Ac), PowerLimits::default());
+        assert_eq!(
+            PROFILE.limits(PowerSource::Battery { capacity: Some(50) }),
+            PowerLimits {
+                max_fps: Some(30),
+                max_brightness: Some(64),
+                paused: false,
+            }
+        );
+    }
+
+    #[test]
+    fn limits_pause_at_low_charge() {
+        let paused = |capacity, pause_below| {
+            PowerProfile { pause_below, ..PROFILE }
+                .limits(PowerSource::Battery { capacity })
+                .paused
+        };
+        assert!(paused(Some(10), 10));
+        assert!(!paused(Some(11), 10));
+        assert!(!paused(None, 10));
+        assert!(!paused(Some(0), 0));
+    }
+}
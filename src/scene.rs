@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::frame::Frame;
+
+/// Commands a running scene can be sent from outside the frame loop.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SceneCommand {
+    /// Start the scene over from its initial state.
+    Reset,
+}
+
+/// Day/Night tile counts for one game, labelled so composite scenes can report several.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Score {
+    pub label: String,
+    pub day: usize,
+    pub night: usize,
+}
+
+/// Something that can be shown on the matrix. The frame loop in `main.rs` owns pacing,
+/// brightness and device recovery; a scene only advances its own state and draws it.
+pub trait Scene {
+    fn name(&self) -> &'static str;
+
+    /// Advance the scene by one frame; `dt` is the wall time since the previous tick.
+    fn tick(&mut self, dt: Duration);
+
+    /// Draw the current state into `frame`, which is cleared before every call.
+    fn draw(&self, frame: &mut Frame);
+
+    /// Called when the canvas changes size, e.g. when a module is plugged in or removed.
+    /// `gap_columns` is the number of hidden columns between the two leftmost modules.
+    fn resize(&mut self, width: usize, height: usize, gap_columns: usize);
+
+    /// Returns `true` if the command was understood by this scene.
+    fn handle_command(&mut self, _command: &SceneCommand) -> bool {
+        false
+    }
+
+    fn scores(&self) -> Vec<Score> {
+        Vec::new()
+    }
+}
+
+/// Formats scores on a single line, e.g. for a side-by-side comparison.
+pub fn score_line(scores: &[Score]) -> String {
+    scores
+        .iter()
+        .map(|score| format!("{}: day {} night {}", score.label, score.day, score.night))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// Divides `width` columns into the left game, the hidden gap and the right game. The gap is
+/// dropped if it does not leave room for both games.
+pub fn split_widths(width: usize, gap_columns: usize) -> (usize, usize, usize) {
+    let gap = if width < gap_columns + 2 { 0 } else { gap_columns };
+    let left = (width - gap) / 2;
+    (left, width - gap - left, gap)
+}
+
+/// Runs two independent scenes next to each other, the left one in columns `0..left_width`
+/// and the right one after `gap` hidden columns.
+pub struct Split {
+    left: Box<dyn Scene>,
+    right: Box<dyn Scene>,
+    left_width: usize,
+    right_width: usize,
+    gap: usize,
+    height: usize,
+}
+
+impl Split {
+    pub fn new(
+        left: Box<dyn Scene>,
+        right: Box<dyn Scene>,
+        left_width: usize,
+        right_width: usize,
+        gap: usize,
+        height: usize,
+    ) -> Self {
+        Split {
+            left,
+            right,
+            left_width,
+            right_width,
+            gap,
+            height,
+        }
+    }
+}
+
+impl Scene for Split {
+    fn name(&self) -> &'static str {
+        "split"
+    }
+
+    fn tick(&mut self, dt: Duration) {
+        self.left.tick(dt);
+        self.right.tick(dt);
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let mut half = Frame::new(self.left_width, self.height);
+        self.left.draw(&mut half);
+        frame.blit(&half, 0);
+
+        let mut half = Frame::new(self.right_width, self.height);
+        self.right.draw(&mut half);
+        frame.blit(&half, self.left_width + self.gap);
+    }
+
+    fn resize(&mut self, width: usize, height: usize, gap_columns: usize) {
+        (self.left_width, self.right_width, self.gap) = split_widths(width, gap_columns);
+        self.height = height;
+        self.left.resize(self.left_width, height, 0);
+        self.right.resize(self.right_width, height, 0);
+    }
+
+    fn handle_command(&mut self, command: &SceneCommand) -> bool {
+        let left = self.left.handle_command(command);
+        let right = self.right.handle_command(command);
+        left || right
+    }
+
+    fn scores(&self) -> Vec<Score> {
+        let label = |side: &str, score: Score| Score {
+            label: side.to_string(),
+            ..score
+        };
+        self.left
+            .scores()
+            .into_iter()
+            .map(|score| label("left", score))
+            .chain(self.right.scores().into_iter().map(|score| label("right", score)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{GameState, Physics};
+
+    fn game(width: usize, height: usize) -> Box<dyn Scene> {
+        Box::new(GameState::with_options(width, height, Physics::default(), Some(1)))
+    }
+
+    #[test]
+    fn split_widths_drop_a_gap_that_leaves_no_room() {
+        assert_eq!(split_widths(18, 0), (9, 9, 0));
+        assert_eq!(split_widths(21, 3), (9, 9, 3));
+        assert_eq!(split_widths(9, 3), (3, 3, 3));
+        assert_eq!(split_widths(4, 3), (2, 2, 0));
+    }
+
+    #[test]
+    fn split_gap_follows_the_modules_on_resize() {
+        let mut split = Split::new(game(9, 34), game(9, 34), 9, 9, 3, 34);
+        split.resize(9, 34, 0);
+        assert_eq!((split.left_width, split.right_width, split.gap), (4, 5, 0));
+        split.resize(21, 34, 3);
+        assert_eq!((split.left_width, split.right_width, split.gap), (9, 9, 3));
+    }
+}
@@ -0,0 +1,55 @@
+use anyhow::Result;
+use log::{error, info, warn};
+use signal_hook::consts::{SIGHUP, SIGTERM, SIGUSR1, SIGUSR2};
+use signal_hook::iterator::Signals;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+
+use crate::control::{Command, Controls};
+
+/// Handles the signals used to manage a background instance on a background thread:
+///
+/// - `SIGTERM` shuts down cleanly, like Ctrl+C.
+/// - `SIGHUP` reapplies the settings returned by `reload`.
+/// - `SIGUSR1` resets the board.
+/// - `SIGUSR2` prints the current state and timing statistics.
+pub fn spawn<F>(shutdown: &'static AtomicBool, controls: Controls, reload: F) -> Result<()>
+where
+    F: Fn() -> Result<Vec<Command>> + Send + 'static,
+{
+    let mut signals = Signals::new([SIGTERM, SIGHUP, SIGUSR1, SIGUSR2])?;
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            match signal {
+                SIGTERM => {
+                    info!("Received SIGTERM, shutting down...");
+                    shutdown.store(true, Ordering::SeqCst);
+                }
+                SIGHUP => {
+                    info!("Received SIGHUP, reloading settings");
+                    match reload() {
+                        Ok(commands) => {
+                            for command in commands {
+                                if let Some(error) = controls.submit(command).error {
+                                    error!("Reload failed: {}", error);
+                                }
+                            }
+                        }
+                        Err(e) => error!("Reload failed: {:#}", e),
+                    }
+                }
+                SIGUSR1 => {
+                    info!("Received SIGUSR1, resetting the board");
+                    controls.submit(Command::Reset);
+                }
+                SIGUSR2 => match controls.submit(Command::Status).status {
+                    Some(status) => info!("{}", status),
+                    None => warn!("Received SIGUSR2 but the frame loop did not respond"),
+                },
+                _ => {}
+            }
+        }
+    });
+    Ok(())
+}
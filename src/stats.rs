@@ -0,0 +1,199 @@
+use serde::Serialize;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::led_matrix::PortStats;
+use crate::pacing::Jitter;
+
+// Upper bounds of the frame time histogram buckets; longer frames land in a final open bucket.
+// 17 and 34 ms sit just above 60 and 30 fps.
+const FRAME_TIME_BUCKETS_MS: [u64; 9] = [5, 10, 17, 20, 25, 34, 50, 100, 250];
+
+/// Frame timing and error counters collected by the frame loop since startup.
+pub struct FrameStats {
+    started: Instant,
+    frames: u64,
+    frame_time: Duration,
+    max_frame_time: Duration,
+    histogram: [u64; FRAME_TIME_BUCKETS_MS.len() + 1],
+    deadline_misses: u64,
+    render_errors: u64,
+}
+
+impl FrameStats {
+    pub fn new(now: Instant) -> Self {
+        FrameStats {
+            started: now,
+            frames: 0,
+            frame_time: Duration::ZERO,
+            max_frame_time: Duration::ZERO,
+            histogram: [0; FRAME_TIME_BUCKETS_MS.len() + 1],
+            deadline_misses: 0,
+            render_errors: 0,
+        }
+    }
+
+    /// Records the time since the previous frame started.
+    pub fn record_frame(&mut self, dt: Duration) {
+        self.frames += 1;
+        self.frame_time += dt;
+        self.max_frame_time = self.max_frame_time.max(dt);
+        let bucket = FRAME_TIME_BUCKETS_MS
+            .iter()
+            .position(|&ms| dt <= Duration::from_millis(ms))
+            .unwrap_or(FRAME_TIME_BUCKETS_MS.len());
+        self.histogram[bucket] += 1;
+    }
+
+    /// Records a frame that started more than a whole frame interval after its deadline.
+    pub fn record_deadline_miss(&mut self) {
+        self.deadline_misses += 1;
+    }
+
+    pub fn record_render_error(&mut self) {
+        self.render_errors += 1;
+    }
+
+    pub fn report(&self, now: Instant, jitter: Jitter, ports: Vec<PortStats>) -> StatsReport {
+        let uptime = now.duration_since(self.started).as_secs_f64();
+        let mean_frame_time = if self.frames > 0 {
+            Duration::from_secs_f64(self.frame_time.as_secs_f64() / self.frames as f64)
+        } else {
+            Duration::ZERO
+        };
+        let histogram = self
+            .histogram
+            .iter()
+            .enumerate()
+            .map(|(i, &count)| HistogramBucket {
+                le_ms: FRAME_TIME_BUCKETS_MS.get(i).copied(),
+                count,
+            })
+            .collect();
+        StatsReport {
+            uptime_secs: uptime,
+            frames: self.frames,
+            fps: if uptime > 0.0 {
+                self.frames as f64 / uptime
+            } else {
+                0.0
+            },
+            mean_frame_time_ms: millis(mean_frame_time),
+            max_frame_time_ms: millis(self.max_frame_time),
+            frame_time_histogram: histogram,
+            deadline_misses: self.deadline_misses,
+            dropped_frames: ports.iter().map(|p| p.dropped_frames).sum(),
+            render_errors: self.render_errors,
+            mean_jitter_ms: millis(jitter.mean()),
+            max_jitter_ms: millis(jitter.max),
+            ports: ports.iter().map(PortReport::from).collect(),
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Snapshot of `FrameStats` and the per-module counters, printed as a summary and written
+/// by `--stats-file`.
+#[derive(Clone, Debug, Serialize)]
+pub struct StatsReport {
+    pub uptime_secs: f64,
+    pub frames: u64,
+    pub fps: f64,
+    pub mean_frame_time_ms: f64,
+    pub max_frame_time_ms: f64,
+    pub frame_time_histogram: Vec<HistogramBucket>,
+    pub deadline_misses: u64,
+    pub dropped_frames: u64,
+    pub render_errors: u64,
+    pub mean_jitter_ms: f64,
+    pub max_jitter_ms: f64,
+    pub ports: Vec<PortReport>,
+}
+
+/// Frames that took at most `le_ms` milliseconds and longer than the previous bucket; the
+/// last bucket has no upper bound.
+#[derive(Clone, Debug, Serialize)]
+pub struct HistogramBucket {
+    pub le_ms: Option<u64>,
+    pub count: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PortReport {
+    pub port: String,
+    pub writes: u64,
+    pub bytes_written: u64,
+    pub mean_write_latency_ms: Option<f64>,
+    pub max_write_latency_ms: Option<f64>,
+    pub write_errors: u64,
+    pub dropped_frames: u64,
+    pub reconnects: u64,
+}
+
+impl From<&PortStats> for PortReport {
+    fn from(stats: &PortStats) -> Self {
+        PortReport {
+            port: stats.port_name.clone(),
+            writes: stats.writes,
+            bytes_written: stats.bytes_written,
+            mean_write_latency_ms: stats.mean_write_latency().map(millis),
+            max_write_latency_ms: (stats.writes > 0).then(|| millis(stats.max_write_latency)),
+            write_errors: stats.write_errors,
+            dropped_frames: stats.dropped_frames,
+            reconnects: stats.reconnects,
+        }
+    }
+}
+
+impl fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} frames in {:.0}s ({:.1} fps), frame time mean {:.1}ms max {:.1}ms, \
+             {} deadline misses, {} dropped, {} render errors, jitter mean {:.2}ms max {:.2}ms",
+            self.frames,
+            self.uptime_secs,
+            self.fps,
+            self.mean_frame_time_ms,
+            self.max_frame_time_ms,
+            self.deadline_misses,
+            self.dropped_frames,
+            self.render_errors,
+            self.mean_jitter_ms,
+            self.max_jitter_ms
+        )?;
+
+        let buckets: Vec<String> = self
+            .frame_time_histogram
+            .iter()
+            .filter(|bucket| bucket.count > 0)
+            .map(|bucket| match bucket.le_ms {
+                Some(ms) => format!("<={}ms: {}", ms, bucket.count),
+                None => format!("longer: {}", bucket.count),
+            })
+            .collect();
+        if !buckets.is_empty() {
+            write!(f, "\n  frame times {}", buckets.join(", "))?;
+        }
+
+        for port in &self.ports {
+            write!(
+                f,
+                "\n  {}: {} writes, {} bytes",
+                port.port, port.writes, port.bytes_written
+            )?;
+            if let (Some(mean), Some(max)) = (port.mean_write_latency_ms, port.max_write_latency_ms) {
+                write!(f, ", latency mean {:.2}ms max {:.2}ms", mean, max)?;
+            }
+            write!(
+                f,
+                ", {} errors, {} dropped, {} reconnects",
+                port.write_errors, port.dropped_frames, port.reconnects
+            )?;
+        }
+        Ok(())
+    }
+}
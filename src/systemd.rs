@@ -0,0 +1,81 @@
+use std::time::{Duration, Instant};
+
+/// Reports readiness and watchdog keep-alives to systemd through `$NOTIFY_SOCKET`, as
+/// `sd_notify` does. Outside of a `Type=notify` service every call does nothing.
+pub struct Notifier {
+    #[cfg(target_os = "linux")]
+    socket: Option<(std::os::unix::net::UnixDatagram, std::os::unix::net::SocketAddr)>,
+    // Half the service's `WatchdogSec`, as systemd recommends.
+    watchdog_interval: Option<Duration>,
+    last_ping: Option<Instant>,
+}
+
+impl Notifier {
+    pub fn from_env() -> Self {
+        let watchdog_interval = std::env::var("WATCHDOG_USEC")
+            .ok()
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|&usec| usec > 0)
+            .map(|usec| Duration::from_micros(usec) / 2);
+        Notifier {
+            #[cfg(target_os = "linux")]
+            socket: connect(),
+            watchdog_interval,
+            last_ping: None,
+        }
+    }
+
+    pub fn ready(&mut self) {
+        self.send("READY=1");
+    }
+
+    pub fn stopping(&mut self) {
+        self.send("STOPPING=1");
+    }
+
+    pub fn status(&mut self, status: &str) {
+        self.send(&format!("STATUS={}", status));
+    }
+
+    /// Called every iteration of the frame loop, so a stuck loop stops the pings and
+    /// systemd restarts the service.
+    pub fn watchdog(&mut self, now: Instant) {
+        let Some(interval) = self.watchdog_interval else {
+            return;
+        };
+        if self
+            .last_ping
+            .is_some_and(|last| now.duration_since(last) < interval)
+        {
+            return;
+        }
+        self.last_ping = Some(now);
+        self.send("WATCHDOG=1");
+    }
+
+    #[cfg(target_os = "linux")]
+    fn send(&mut self, state: &str) {
+        if let Some((socket, address)) = &self.socket {
+            if let Err(e) = socket.send_to_addr(state.as_bytes(), address) {
+                log::warn!("Failed notifying systemd: {}", e);
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn send(&mut self, _state: &str) {}
+}
+
+// `NOTIFY_SOCKET` is a path, or an abstract socket name when it starts with `@`.
+#[cfg(target_os = "linux")]
+fn connect() -> Option<(std::os::unix::net::UnixDatagram, std::os::unix::net::SocketAddr)> {
+    use std::os::linux::net::SocketAddrExt;
+    use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+    let path = std::env::var("NOTIFY_SOCKET").ok()?;
+    let address = match path.strip_prefix('@') {
+        Some(name) => SocketAddr::from_abstract_name(name.as_bytes()).ok()?,
+        None => SocketAddr::from_pathname(&path).ok()?,
+    };
+    Some((UnixDatagram::unbound().ok()?, address))
+}
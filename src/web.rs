@@ -0,0 +1,218 @@
+use anyhow::{Context, Result};
+use log::debug;
+use serde_json::json;
+use std::io::Write;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::control::{Command, Controls, Reply};
+use crate::http;
+use crate::led_matrix::{FrameMirror, MirroredFrame};
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+const FRAME_WAIT: Duration = Duration::from_millis(500);
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Serves the dashboard: a page with a live mirror of the matrix, the scores over time and
+/// controls. Frames and status are streamed as Server-Sent Events from `/events`; commands
+/// use the control socket's JSON format, posted to `/command`.
+pub fn spawn(address: SocketAddr, mirror: Arc<FrameMirror>, controls: Controls) -> Result<()> {
+    let listener =
+        TcpListener::bind(address).with_context(|| format!("Failed binding {}", address))?;
+    serve_on(listener, mirror, controls);
+    Ok(())
+}
+
+fn serve_on(listener: TcpListener, mirror: Arc<FrameMirror>, controls: Controls) {
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mirror = mirror.clone();
+            let controls = controls.clone();
+            thread::spawn(move || {
+                if let Err(e) = serve(stream, &mirror, &controls) {
+                    debug!("Dashboard request failed: {}", e);
+                }
+            });
+        }
+    });
+}
+
+fn serve(stream: TcpStream, mirror: &FrameMirror, controls: &Controls) -> Result<()> {
+    let request = http::read_request(&stream)?;
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/") => http::respond(&stream, "200 OK", "text/html; charset=utf-8", DASHBOARD),
+        ("GET", "/events") => stream_events(stream, mirror, controls),
+        ("POST", "/command") => {
+            // Browsers only send JSON cross-origin after a preflight, which is never granted,
+            // so other sites cannot drive the game through a visitor's browser.
+            let is_json = request
+                .content_type
+                .is_some_and(|t| t.starts_with("application/json"));
+            let (status, reply) = if !is_json {
+                ("415 Unsupported Media Type", Reply::error("expected a JSON command"))
+            } else {
+                match serde_json::from_slice::<Command>(&request.body) {
+                    Ok(Command::Quit) => ("403 Forbidden", Reply::error("quit is not available here")),
+                    Ok(command) => ("200 OK", controls.submit(command)),
+                    Err(e) => ("400 Bad Request", Reply::error(format!("invalid command: {}", e))),
+                }
+            };
+            http::respond(&stream, status, "application/json", &serde_json::to_string(&reply)?)
+        }
+        _ => http::not_found(&stream),
+    }
+}
+
+// Sends every new frame, and the status about once a second, until the browser goes away.
+fn stream_events(mut stream: TcpStream, mirror: &FrameMirror, controls: &Controls) -> Result<()> {
+    stream.set_write_timeout(None)?;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\n\r\n"
+    )?;
+
+    let mut generation = 0;
+    let mut last_status: Option<Instant> = None;
+    loop {
+        if let Some(frame) = mirror.wait_newer(generation, FRAME_WAIT) {
+            generation = frame.generation;
+            write!(stream, "event: frame\ndata: {}\n\n", frame_json(&frame))?;
+        }
+        if last_status.is_none_or(|last| last.elapsed() >= STATUS_INTERVAL) {
+            if let Some(status) = controls.submit(Command::Status).status {
+                write!(stream, "event: status\ndata: {}\n\n", serde_json::to_string(&status)?)?;
+            }
+            last_status = Some(Instant::now());
+        }
+        stream.flush()?;
+    }
+}
+
+fn frame_json(frame: &MirroredFrame) -> serde_json::Value {
+    let modules: Vec<_> = frame
+        .modules
+        .iter()
+        .map(|module| {
+            let bits: String = module.bits.iter().map(|b| format!("{:02x}", b)).collect();
+            json!({
+                "x": module.x,
+                "y": module.y,
+                "width": module.width,
+                "height": module.height,
+                "flip_horizontal": module.orientation.flip_horizontal,
+                "flip_vertical": module.orientation.flip_vertical,
+                "bits": bits,
+            })
+        })
+        .collect();
+    json!({
+        "width": frame.width,
+        "height": frame.height,
+        "modules": modules,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::Orientation;
+    use crate::led_matrix::PackedModule;
+    use std::io::Read;
+
+    fn post_command(address: SocketAddr, content_type: &str, body: &str) -> (String, Reply) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "POST /command HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+            content_type,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap().to_string();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn command_endpoint_rejects_what_it_must_not_run() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (controls, requests) = Controls::new();
+        serve_on(listener, Arc::new(FrameMirror::default()), controls);
+
+        let (status, reply) = post_command(address, "text/plain", r#"{"cmd":"reset"}"#);
+        assert_eq!(status, "HTTP/1.1 415 Unsupported Media Type");
+        assert!(!reply.ok);
+
+        let (status, reply) = post_command(address, "application/json", r#"{"cmd":"quit"}"#);
+        assert_eq!(status, "HTTP/1.1 403 Forbidden");
+        assert!(!reply.ok);
+
+        let (status, reply) = post_command(address, "application/json", r#"{"cmd":"explode"}"#);
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        assert!(reply.error.unwrap().starts_with("invalid command"));
+
+        assert!(requests.try_recv().is_err());
+    }
+
+    #[test]
+    fn command_endpoint_forwards_json_commands() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (controls, requests) = Controls::new();
+        serve_on(listener, Arc::new(FrameMirror::default()), controls);
+        let frame_loop = thread::spawn(move || {
+            let request = requests.recv().unwrap();
+            let command = request.command.clone();
+            request.respond(Reply::ok());
+            command
+        });
+
+        let body = r#"{"cmd":"brightness","percent":40}"#;
+        let (status, reply) = post_command(address, "application/json; charset=utf-8", body);
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert!(reply.ok);
+        assert_eq!(frame_loop.join().unwrap(), Command::Brightness { percent: 40 });
+    }
+
+    #[test]
+    fn frame_json_sends_packed_bits_as_hex() {
+        let frame = MirroredFrame {
+            generation: 7,
+            width: 21,
+            height: 34,
+            modules: vec![PackedModule {
+                x: 12,
+                y: 0,
+                width: 9,
+                height: 34,
+                orientation: Orientation {
+                    flip_horizontal: true,
+                    flip_vertical: false,
+                },
+                bits: vec![0x01, 0xa0, 0x00, 0xff],
+            }],
+        };
+        assert_eq!(
+            frame_json(&frame),
+            json!({
+                "width": 21,
+                "height": 34,
+                "modules": [{
+                    "x": 12,
+                    "y": 0,
+                    "width": 9,
+                    "height": 34,
+                    "flip_horizontal": true,
+                    "flip_vertical": false,
+                    "bits": "01a000ff",
+                }],
+            })
+        );
+    }
+}